@@ -0,0 +1,302 @@
+use crate::machine::{ImageError, RuntimeError, RuntimeErrorKind, IMAGE_VERSION};
+use crate::parse::{Delimiter, ParseError, Span, StreamError, SymItem, SymParseError};
+use crate::primitives::{DecodingError, EncodingError, MinstSymItemError};
+
+use std::fmt::Write;
+
+// An error report in the style of rustc: a primary message, the source range it
+// is about and any notes explaining how to fix it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub message: String,
+  pub span: Option<Span>,
+  pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn new(message: impl Into<String>) -> Self {
+    Diagnostic {
+      message: message.into(),
+      span: None,
+      notes: vec![],
+    }
+  }
+
+  pub fn with_span(mut self, span: Option<Span>) -> Self {
+    self.span = span;
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+
+  // Renders the report. `origin` names the source (usually a file path); when
+  // `source` is given and holds the span, the offending line is quoted with the
+  // span underlined. Spans running over several lines are underlined up to the
+  // end of their first line.
+  //
+  //   error: no definition for `cdr`
+  //    --> lib.mmm:3:2
+  //     |
+  //   3 | (cdr (a b))
+  //     |  ^^^
+  //     = note: define it first with `(macro (cdr args...) body...)`
+  pub fn render(&self, origin: &str, source: Option<&str>) -> String {
+    let mut out = String::new();
+    writeln!(out, "error: {}", self.message).unwrap();
+
+    let snippet = self.span.and_then(|span| source.and_then(|source| Self::snippet(source, span)));
+    let gutter = snippet.as_ref().map_or(1, |(line_no, _, _, _)| line_no.to_string().len());
+    let pad = " ".repeat(gutter);
+
+    if let Some(span) = self.span {
+      writeln!(out, "{}--> {}:{}:{}", pad, origin, span.start.line, span.start.column).unwrap();
+    }
+    if let Some((line_no, line, column, width)) = snippet {
+      writeln!(out, "{} |", pad).unwrap();
+      writeln!(out, "{} | {}", line_no, line).unwrap();
+      writeln!(out, "{} | {}{}", pad, " ".repeat(column), "^".repeat(width)).unwrap();
+    }
+    for note in self.notes.iter() {
+      writeln!(out, "{} = note: {}", pad, note).unwrap();
+    }
+    out
+  }
+
+  // Line number, line text, caret column and caret width, or `None` when the span
+  // does not fit the source.
+  fn snippet(source: &str, span: Span) -> Option<(usize, &str, usize, usize)> {
+    let line = source.lines().nth(span.start.line.checked_sub(1)?)?;
+    let line_start = source.get(..span.start.offset)?.rfind('\n').map_or(0, |idx| idx + 1);
+    let column = source.get(line_start..span.start.offset)?.chars().count();
+    let line_end = line_start + line.len();
+    let width = source.get(span.start.offset..span.end.offset.clamp(span.start.offset, line_end))?
+      .chars().count();
+    Some((span.start.line, line.trim_end_matches('\r'), column, width.max(1)))
+  }
+}
+
+pub trait Diagnose {
+  fn diagnose(&self) -> Diagnostic;
+}
+
+const INSTRUCTIONS: &str = "known instructions are .DEFINE, .EXPAND, .INDEX, .CONTEXT, .RETURN, .FRAME and .SPLIT";
+
+// How an instruction is meant to be written, for notes on malformed ones.
+fn instruction_usage(name: &str) -> Option<&'static str> {
+  match name {
+    ".DEFINE" => Some("`.DEFINE` takes an optional frame to scope the definition to"),
+    ".EXPAND" => Some("`.EXPAND` takes an optional element index"),
+    ".INDEX" => Some("`.INDEX` takes a frame and an optional element index"),
+    ".CONTEXT" => Some("`.CONTEXT` takes no arguments or a range of two frames"),
+    ".RETURN" => Some("`.RETURN` takes no arguments, an element index or a range of two indices"),
+    ".FRAME" => Some("`.FRAME` takes an element index and an optional index within that element"),
+    ".SPLIT" => Some("`.SPLIT` takes an element index"),
+    _ => None,
+  }
+}
+
+fn diagnose_invalid_instr(sym: &SymItem) -> Diagnostic {
+  let diagnostic = Diagnostic::new(format!("invalid machine instruction `{}`", sym)).with_span(sym.span());
+  let list = match sym.as_list() {
+    Some(list) => list,
+    None => return diagnostic,
+  };
+
+  let name = sym.index_early(0).and_then(|name| name.as_str()).unwrap_or("");
+  let args = list.iter().skip(1)
+    .filter_map(|arg| arg.into_inner_early().and_then(|arg| arg.as_number()))
+    .collect::<Vec<_>>();
+
+  if name == ".RETURN" && args.len() == 2 && args[0] == args[1] {
+    return diagnostic.with_note("`.RETURN` with two equal bounds is rejected; use the one-argument form");
+  }
+  match instruction_usage(name) {
+    Some(usage) => diagnostic.with_note(usage),
+    None => diagnostic.with_note(INSTRUCTIONS),
+  }
+}
+
+impl Diagnose for SymParseError {
+  fn diagnose(&self) -> Diagnostic {
+    let diagnostic = Diagnostic::new(self.to_string()).with_span(Some(self.span()));
+    match self {
+      SymParseError::SymItemExtraInput(_, _) =>
+	diagnostic.with_note("only a single item is expected here; use a program reader for several top-level forms"),
+      SymParseError::SymItemEOF(_, _) => diagnostic.with_note("the input is empty"),
+      SymParseError::SymListEOF(_, _) => diagnostic.with_note("this list is missing its closing bracket"),
+      SymParseError::SymAtomNoTerminal(_, _) => diagnostic.with_note("atoms end at whitespace, a bracket, a string literal or a `;` comment"),
+      SymParseError::SymAtomEOF(_, _) => diagnostic.with_note("the input ended inside an atom"),
+      SymParseError::InvalidStartOfInput(close, _) => diagnostic.with_note(format!("this `{}` does not close any list", close)),
+      SymParseError::MismatchedBracket(open, open_span, _, _) =>
+	diagnostic.with_note(format!("the list opened with `{}` at {} needs a matching `{}`",
+				     open, open_span, Delimiter::from_open(*open).unwrap().close())),
+      SymParseError::UnterminatedBlockComment(_) =>
+	diagnostic.with_note("block comments nest; every `#|` needs a matching `|#`"),
+      SymParseError::DatumCommentEOF(_) => diagnostic.with_note("`#;` comments out the item that follows it"),
+      SymParseError::SymStrEOF(_) => diagnostic.with_note("this string literal is missing its closing `\"`"),
+      SymParseError::InvalidNumber(_) =>
+	diagnostic.with_note("numbers are decimal, 0x hex or 0b binary integers that fit in 64 bits, or decimal n/d rationals with d non-zero"),
+      SymParseError::InvalidDottedList(_) =>
+	diagnostic.with_note("a `.` goes between the last item of a list and its tail, as in `(a b . c)`"),
+      SymParseError::QuoteEOF(_) => diagnostic.with_note("a quote prefix applies to the item that follows it"),
+      SymParseError::InvalidEscape(_) =>
+	diagnostic.with_note("escapes are \\n, \\t, \\r, \\0, \\\", \\\\ and \\u{...} with one to six hex digits"),
+    }
+  }
+}
+
+impl Diagnose for ParseError {
+  fn diagnose(&self) -> Diagnostic {
+    match self {
+      ParseError::Syntax(err) => err.diagnose(),
+      ParseError::NotAnInstruction(sym) =>
+	Diagnostic::new(format!("`{}` is not a machine instruction", sym))
+	  .with_span(sym.span())
+	  .with_note("machine instructions start with a `.`"),
+      ParseError::InvalidInstr(sym) => diagnose_invalid_instr(sym),
+      ParseError::InvalidArgs(sym) =>
+	Diagnostic::new(format!("invalid instruction arguments in `{}`", sym))
+	  .with_span(sym.span())
+	  .with_note("instruction arguments are integers, and only those from -8192 to 8191 fit the 14-bit fields of an encoded instruction"),
+    }
+  }
+}
+
+impl Diagnose for StreamError {
+  fn diagnose(&self) -> Diagnostic {
+    match self {
+      StreamError::Io(err) => Diagnostic::new(format!("cannot read input: {}", err)),
+      StreamError::Syntax(err) => err.diagnose(),
+      StreamError::Incomplete(err) => {
+	let diagnostic = err.diagnose();
+	Diagnostic { message: format!("incomplete input: {}", diagnostic.message), ..diagnostic }
+      },
+    }
+  }
+}
+
+impl<'a> Diagnose for MinstSymItemError<'a> {
+  fn diagnose(&self) -> Diagnostic {
+    ParseError::from(self.clone()).diagnose()
+  }
+}
+
+impl Diagnose for EncodingError {
+  fn diagnose(&self) -> Diagnostic {
+    let diagnostic = Diagnostic::new(self.to_string());
+    match self {
+      EncodingError::InvalidArg(_) =>
+	diagnostic.with_note("instruction words hold arguments as signed 14-bit fields, from -8192 to 8191"),
+      EncodingError::InvalidRange(_, _) =>
+	diagnostic.with_note("a `.RETURN` range with two equal bounds is encoded as the one-argument form"),
+      EncodingError::InvalidConstant(_) =>
+	diagnostic.with_note("constant references hold the pool index in 29 bits"),
+    }
+  }
+}
+
+impl Diagnose for ImageError {
+  fn diagnose(&self) -> Diagnostic {
+    let diagnostic = Diagnostic::new(self.to_string());
+    match self {
+      ImageError::Encoding(err) => err.diagnose(),
+      ImageError::Decoding(err) => err.diagnose().with_note("the image is corrupt"),
+      ImageError::MachineBusy => diagnostic.with_note("images are saved between top-level forms"),
+      ImageError::Unserializable(elem) =>
+	diagnostic.with_span(elem.span()).with_note("placeholders for text that failed to parse cannot be saved"),
+      ImageError::InvalidBody(name) =>
+	diagnostic.with_span(name.span()).with_note("macro bodies must be proper lists"),
+      ImageError::InvalidMagic => diagnostic.with_note("images start with the bytes `SYMI`"),
+      ImageError::UnsupportedVersion(_) =>
+	diagnostic.with_note(format!("this build reads version {} images; rebuild the image from source", IMAGE_VERSION)),
+      _ => diagnostic.with_note("the image is corrupt"),
+    }
+  }
+}
+
+impl Diagnose for DecodingError {
+  fn diagnose(&self) -> Diagnostic {
+    let diagnostic = Diagnostic::new(self.to_string());
+    match self {
+      DecodingError::InvalidInstEncoding(_) =>
+	diagnostic.with_note("opcodes 0 to 6 encode .DEFINE, .EXPAND, .INDEX, .CONTEXT, .RETURN, .FRAME and .SPLIT"),
+      DecodingError::InvalidInstWithArgs(_) =>
+	diagnostic.with_note("argument fields an instruction form does not use must be zero, and `.SPLIT` has no long form"),
+      DecodingError::InvalidConstant(_) =>
+	diagnostic.with_note("constant references index the pool the code was compiled or loaded with"),
+    }
+  }
+}
+
+impl Diagnose for RuntimeError {
+  fn diagnose(&self) -> Diagnostic {
+    let diagnostic = Diagnostic::new(self.kind.to_string()).with_span(self.span);
+    match &*self.kind {
+      RuntimeErrorKind::CompoundListAsMacroError(_) =>
+	diagnostic.with_note("the first element of a macro call must name the macro"),
+      RuntimeErrorKind::UnknownDef(name) =>
+	diagnostic.with_note(format!("define it first with `(macro ({} args...) body...)`", name)),
+      RuntimeErrorKind::EmptyMacroCall => diagnostic.with_note("`()` is data and cannot be expanded"),
+      RuntimeErrorKind::ArityMismatch(_, _) =>
+	diagnostic.with_note("a call may pass more arguments than its form names, but not fewer"),
+      RuntimeErrorKind::EmptyFrame => diagnostic.with_note("an instruction needed more elements than the active frame holds"),
+      RuntimeErrorKind::NoSuchFrame(_) =>
+	diagnostic.with_note("frames are numbered from the active frame, which is 0, down to the root frame"),
+      RuntimeErrorKind::IndexOutOfRange(_) =>
+	diagnostic.with_note("negative indices count back from the end of the frame, -1 being the last element"),
+      RuntimeErrorKind::RangeOutOfBounds(_, _) =>
+	diagnostic.with_note("ranges are inclusive and both bounds must lie within the frame"),
+      RuntimeErrorKind::NotAList(_) =>
+	diagnostic.with_note("macro calls, frames opened by `.CONTEXT` or `.FRAME` and `,@` splices must be proper lists, and `.SPLIT` needs a non-empty list"),
+      RuntimeErrorKind::InvalidDefinition(_) =>
+	diagnostic.with_note("`.DEFINE` expects a name, an argument form and a body, both proper lists, on top of the active frame"),
+      RuntimeErrorKind::Redefinition(_) =>
+	diagnostic.with_note("the redefinition policy rejects defining a name twice in one scope"),
+      RuntimeErrorKind::InvalidQuote(_) =>
+	diagnostic.with_note("quote forms take one operand, and unquotes are only valid inside a quasiquote"),
+      RuntimeErrorKind::UnboundName(_) =>
+	diagnostic.with_note("unquote a name from the running macro's form, or an integer to index the active frame"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::primitives::MetaElement;
+
+  #[test]
+  fn render() {
+    let source = "(macro (id x) (.RETURN 1))\n(id (a\n b c]";
+    let err = SymItem::parse(source).unwrap_err();
+    assert_eq!(err.diagnose().render("prog.mmm", Some(source)), "\
+error: Extra input after SymItem.
+ --> prog.mmm:2:1
+  |
+2 | (id (a
+  | ^^^^^^
+  = note: only a single item is expected here; use a program reader for several top-level forms
+");
+
+    // Runtime errors point at the offending element, with a gutter as wide as the
+    // line number; without the source only the location is given.
+    let mut meta = crate::machine::tests::boot();
+    meta.feed(MetaElement::parse("(macro (sp x) (.SPLIT 1))").unwrap());
+    meta.run().unwrap();
+    let source = "(sp\n\n\n\n\n\n\n\n\n  xs)";
+    meta.feed(MetaElement::parse(source).unwrap());
+    let diagnostic = meta.run().unwrap_err().diagnose();
+    assert_eq!(diagnostic.render("prog.mmm", Some(source)), "\
+error: expected a list, found `xs`
+  --> prog.mmm:10:3
+   |
+10 |   xs)
+   |   ^^
+   = note: macro calls, frames opened by `.CONTEXT` or `.FRAME` and `,@` splices must be proper lists, and `.SPLIT` needs a non-empty list
+");
+    assert_eq!(diagnostic.render("prog.mmm", None).lines().nth(1), Some(" --> prog.mmm:10:3"));
+  }
+}
@@ -0,0 +1,7 @@
+#![feature(iter_advance_by)]
+#![feature(iterator_try_collect)]
+
+pub mod diagnostics;
+pub mod machine;
+pub mod parse;
+pub mod primitives;
@@ -0,0 +1,224 @@
+use crate::parse::Symbol;
+use crate::primitives::{Bytecode, MetaElement};
+
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub type MetaDef = (MetaElement, MetaElement, MetaElement);
+
+// What happens when a name is defined again within the scope that already holds it.
+// Definitions in an inner scope always shadow the outer ones, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedefinitionPolicy {
+  Error,
+  Shadow,
+  Replace,
+}
+
+#[derive(Debug)]
+pub struct RedefinitionError(pub MetaElement);
+
+struct Binding {
+  scope: usize,
+  order: usize,
+  def: MetaDef,
+  // The body compiled on first use, or `None` when it does not compile.
+  code: OnceCell<Option<Rc<Bytecode>>>,
+}
+
+impl Binding {
+  fn new(scope: usize, order: usize, def: MetaDef, code: OnceCell<Option<Rc<Bytecode>>>) -> Self {
+    Binding { scope, order, def, code }
+  }
+
+  fn bytecode(&self) -> Option<Rc<Bytecode>> {
+    self.code.get_or_init(|| {
+      let body = self.def.2.elements()?;
+      Bytecode::compile(&body).ok().map(Rc::new)
+    }).clone()
+  }
+}
+
+// Definition table with shallow binding. Every name maps to the stack of its
+// bindings, innermost last, so a lookup is a single hash probe. Scopes
+// are numbered by the stack index of the frame owning them; scope 0 is the root frame
+// and holds the global definitions.
+pub struct DefTable {
+  bindings: HashMap<Symbol, Vec<Binding>>,
+  scopes: Vec<Vec<Symbol>>,
+  policy: RedefinitionPolicy,
+  next_order: usize,
+}
+
+impl DefTable {
+  pub fn new(policy: RedefinitionPolicy) -> Self {
+    DefTable {
+      bindings: HashMap::new(),
+      scopes: vec![vec![]],
+      policy,
+      next_order: 0,
+    }
+  }
+
+  pub fn policy(&self) -> RedefinitionPolicy {
+    self.policy
+  }
+
+  pub fn set_policy(&mut self, policy: RedefinitionPolicy) {
+    self.policy = policy;
+  }
+
+  // Adds `def` to `scope`. The definition's name must be an atom.
+  pub fn define(&mut self, scope: usize, def: MetaDef) -> Result<(), RedefinitionError> {
+    self.bind(scope, def, OnceCell::new())
+  }
+
+  // Adds `def` along with its body already compiled, as when loading an image.
+  pub fn define_compiled(&mut self, scope: usize, def: MetaDef, code: Rc<Bytecode>) -> Result<(), RedefinitionError> {
+    self.bind(scope, def, OnceCell::from(Some(code)))
+  }
+
+  // Whether the policy lets `name` be defined in `scope`.
+  pub fn admits(&self, scope: usize, name: Symbol) -> bool {
+    self.policy != RedefinitionPolicy::Error
+      || !self.bindings.get(&name).is_some_and(|bindings| bindings.iter().any(|binding| binding.scope == scope))
+  }
+
+  fn bind(&mut self, scope: usize, def: MetaDef, code: OnceCell<Option<Rc<Bytecode>>>) -> Result<(), RedefinitionError> {
+    let symbol = def.0.as_symbol().unwrap();
+    if !self.admits(scope, symbol) {
+      return Err(RedefinitionError(def.0));
+    }
+    let order = self.next_order;
+    let bindings = self.bindings.entry(symbol).or_default();
+
+    let existing = bindings.iter().rposition(|binding| binding.scope == scope);
+    match (existing, self.policy) {
+      (Some(idx), RedefinitionPolicy::Replace) => {
+	bindings[idx] = Binding::new(scope, order, def, code);
+      },
+      _ => {
+	// Keep the bindings ordered by scope so that the innermost one stays last
+	// even when an outer scope is extended from within an inner one.
+	let idx = bindings.iter().rposition(|binding| binding.scope <= scope).map_or(0, |idx| idx + 1);
+	bindings.insert(idx, Binding::new(scope, order, def, code));
+	if self.scopes.len() <= scope {
+	  self.scopes.resize(scope + 1, vec![]);
+	}
+	self.scopes[scope].push(symbol);
+      },
+    }
+    self.next_order += 1;
+    Ok(())
+  }
+
+  pub fn lookup(&self, name: Symbol) -> Option<&MetaDef> {
+    self.bindings.get(&name)?.last().map(|binding| &binding.def)
+  }
+
+  // Bytecode for the body of the visible definition of `name`, compiled the first
+  // time it is asked for.
+  pub fn bytecode(&self, name: Symbol) -> Option<Rc<Bytecode>> {
+    self.bindings.get(&name)?.last()?.bytecode()
+  }
+
+  // Drops every scope owned by a frame at stack index `depth` or above.
+  pub fn close_scopes(&mut self, depth: usize) {
+    let depth = depth.max(1);
+    if self.scopes.len() <= depth {
+      return;
+    }
+    for symbol in self.scopes.drain(depth..).flatten() {
+      if let Some(bindings) = self.bindings.get_mut(&symbol) {
+	bindings.retain(|binding| binding.scope < depth);
+      }
+    }
+  }
+
+  // Every definition still in the table, shadowed ones included, in the order they
+  // were made.
+  pub fn iter(&self) -> impl Iterator<Item = &MetaDef> {
+    self.ordered().into_iter().map(|binding| &binding.def)
+  }
+
+  // The same definitions with their scopes and compiled bodies.
+  pub fn compiled(&self) -> impl Iterator<Item = (usize, &MetaDef, Option<Rc<Bytecode>>)> {
+    self.ordered().into_iter().map(|binding| (binding.scope, &binding.def, binding.bytecode()))
+  }
+
+  fn ordered(&self) -> Vec<&Binding> {
+    let mut bindings = self.bindings.values().flatten().collect::<Vec<_>>();
+    bindings.sort_by_key(|binding| binding.order);
+    bindings
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine::tests::{boot, run};
+
+  fn def(name: &str, body: &str) -> MetaDef {
+    (MetaElement::parse(name).unwrap(), MetaElement::parse("(x)").unwrap(), MetaElement::parse(body).unwrap())
+  }
+
+  fn body(defs: &DefTable, name: &str) -> Option<String> {
+    defs.lookup(Symbol::intern(name)).map(|def| def.2.to_string())
+  }
+
+  #[test]
+  fn policies() {
+    let mut defs = DefTable::new(RedefinitionPolicy::Error);
+    defs.define(0, def("f", "(a)")).unwrap();
+    assert_eq!(defs.define(0, def("f", "(b)")).unwrap_err().0.to_string(), "f");
+    // Inner scopes shadow whatever the policy.
+    defs.define(1, def("f", "(c)")).unwrap();
+    assert_eq!(body(&defs, "f").as_deref(), Some("(c)"));
+
+    let mut defs = DefTable::new(RedefinitionPolicy::Shadow);
+    defs.define(0, def("f", "(a)")).unwrap();
+    defs.define(0, def("f", "(b)")).unwrap();
+    assert_eq!(body(&defs, "f").as_deref(), Some("(b)"));
+    assert_eq!(defs.iter().count(), 2);
+
+    let mut defs = DefTable::new(RedefinitionPolicy::Replace);
+    defs.define(0, def("f", "(a)")).unwrap();
+    defs.define(0, def("f", "(b)")).unwrap();
+    assert_eq!(body(&defs, "f").as_deref(), Some("(b)"));
+    assert_eq!(defs.iter().map(|def| def.2.to_string()).collect::<Vec<_>>(), ["(b)"]);
+  }
+
+  #[test]
+  fn scopes() {
+    let mut defs = DefTable::new(RedefinitionPolicy::Shadow);
+    defs.define(0, def("f", "(global)")).unwrap();
+    defs.define(2, def("f", "(inner)")).unwrap();
+    // Extending an outer scope from an inner one keeps the inner binding visible.
+    defs.define(1, def("f", "(outer)")).unwrap();
+    defs.define(2, def("g", "(inner)")).unwrap();
+    assert_eq!(body(&defs, "f").as_deref(), Some("(inner)"));
+
+    defs.close_scopes(2);
+    assert_eq!(body(&defs, "f").as_deref(), Some("(outer)"));
+    assert_eq!(body(&defs, "g"), None);
+    // The root scope is never closed.
+    defs.close_scopes(0);
+    assert_eq!(body(&defs, "f").as_deref(), Some("(global)"));
+  }
+
+  // A definition made with `.DEFINE 0` inside a macro goes away with its frame.
+  #[test]
+  fn local_definitions() {
+    let mut meta = boot();
+    let program = "
+      (macro (outer x)
+	'local '(y) '((.RETURN 1))
+	(.DEFINE 0)
+	(local q)
+	(.RETURN -1))
+      (outer a)";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("(q)"));
+    assert_eq!(run(&mut meta, "(local z)"), Err("no definition for `local`".to_string()));
+  }
+}
@@ -0,0 +1,75 @@
+use crate::primitives::MetaElement;
+
+// Side effect of a single machine step. Frames are identified by their stack index,
+// the root frame being 0.
+#[derive(Debug, Clone)]
+pub enum MachineEffect {
+  FramePushed(usize),
+  FramePopped(usize),
+  ElementPushed(MetaElement),
+  ElementPopped(MetaElement),
+  Defined(MetaElement),
+}
+
+#[derive(Debug, Clone)]
+pub struct StepEvent {
+  pub executed: MetaElement,
+  pub effects: Vec<MachineEffect>,
+}
+
+impl StepEvent {
+  pub fn frames_pushed(&self) -> usize {
+    self.effects.iter().filter(|effect| matches!(effect, MachineEffect::FramePushed(_))).count()
+  }
+
+  pub fn frames_popped(&self) -> usize {
+    self.effects.iter().filter(|effect| matches!(effect, MachineEffect::FramePopped(_))).count()
+  }
+
+  pub fn definitions(&self) -> impl Iterator<Item = &MetaElement> {
+    self.effects.iter().filter_map(|effect| {
+      if let MachineEffect::Defined(name) = effect { Some(name) } else { None }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine::tests::boot;
+
+  fn describe(effect: &MachineEffect) -> String {
+    match effect {
+      MachineEffect::FramePushed(depth) => format!("push frame {}", depth),
+      MachineEffect::FramePopped(depth) => format!("pop frame {}", depth),
+      MachineEffect::ElementPushed(elem) => format!("push {}", elem),
+      MachineEffect::ElementPopped(elem) => format!("pop {}", elem),
+      MachineEffect::Defined(name) => format!("define {}", name),
+    }
+  }
+
+  #[test]
+  fn stepping() {
+    let mut meta = boot();
+    meta.feed(MetaElement::parse("(k a)").unwrap());
+    meta.feed(MetaElement::parse("(start k (x) ((.RETURN 1)))").unwrap());
+    let expected: [(&str, &[&str]); 4] = [
+      ("(start k (x) ((.RETURN 1)))", &["pop (start k (x) ((.RETURN 1)))", "push frame 1"]),
+      ("(.DEFINE)", &["pop ((.RETURN 1))", "pop (x)", "pop k", "define k", "pop frame 1"]),
+      ("(k a)", &["pop (k a)", "push frame 1"]),
+      ("(.RETURN 1)", &["pop frame 1", "push a"]),
+    ];
+    let mut events = vec![];
+    for (executed, effects) in expected {
+      let event = meta.step().unwrap();
+      assert_eq!(event.executed.to_string(), executed);
+      assert_eq!(event.effects.iter().map(describe).collect::<Vec<_>>(), effects, "step {}", executed);
+      events.push(event);
+    }
+    assert_eq!(events.iter().map(|event| (event.frames_pushed(), event.frames_popped())).collect::<Vec<_>>(),
+	       [(1, 0), (0, 1), (1, 0), (0, 1)]);
+    assert_eq!(events[1].definitions().map(|name| name.to_string()).collect::<Vec<_>>(), ["k"]);
+    assert!(meta.is_idle());
+    assert_eq!(meta.frame().iter().map(|elem| elem.to_string()).collect::<Vec<_>>(), ["a"]);
+  }
+}
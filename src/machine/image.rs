@@ -0,0 +1,603 @@
+use super::{ExecutionMode, MetaMachine, RedefinitionPolicy};
+use super::defs::DefTable;
+use crate::parse::{Delimiter, Symbol, SymAtom, SymItem, SymList, SymListItem, SymStr};
+use crate::primitives::{Bytecode, DecodingError, EncodingError, MInstEncoding, MacroInstruction, MetaElement};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+// Layout of an image, every integer little-endian:
+//
+//   header      magic "SYMI", u16 version, u8 flags, u8 redefinition policy
+//   symbols     u32 count, then each name as u32 length and UTF-8 bytes
+//   constants   u32 count, then each element (below)
+//   defs        u32 count, then each definition as u32 scope, u32 name symbol,
+//               u32 form constant, u32 body length and the body's instruction
+//               words, whose constant references index the constant pool
+//   stack       only with `FLAG_STACK`: u32 frame count, then each frame as
+//               u32 length and that many constant indices, root frame first
+//
+// An element is a tag byte followed by its contents:
+//
+//   atom         u32 symbol
+//   string       u32 length and UTF-8 bytes
+//   list         u8 delimiter, u32 item count, the items, u8 1 and the tail for
+//                an improper list or u8 0
+//   instruction  u32 instruction word
+//   wide         u32 length and the instruction's text, for instructions with
+//                arguments too large for an instruction word
+//
+// Spans are not kept, so errors in code loaded from an image point nowhere.
+pub const IMAGE_MAGIC: [u8; 4] = *b"SYMI";
+pub const IMAGE_VERSION: u16 = 1;
+
+const FLAG_STACK: u8 = 1;
+
+const TAG_ATOM: u8 = 0;
+const TAG_STR: u8 = 1;
+const TAG_LIST: u8 = 2;
+const TAG_INSTR: u8 = 3;
+const TAG_WIDE_INSTR: u8 = 4;
+
+// Elements are written, displayed and dropped recursively, so lists nested
+// deeper than this are refused both when saving and when loading.
+const MAX_NESTING: usize = 512;
+
+#[derive(Debug)]
+pub enum ImageError {
+  // Saving
+  MachineBusy,
+  Unserializable(MetaElement),
+  InvalidBody(MetaElement),
+  Encoding(EncodingError),
+  // Loading
+  InvalidMagic,
+  UnsupportedVersion(u16),
+  Truncated,
+  TrailingBytes(usize),
+  InvalidUtf8,
+  InvalidTag(u8),
+  InvalidSymbol(u32),
+  InvalidConstant(u32),
+  InvalidElement(String),
+  TooDeep,
+  InvalidDefinition(MetaElement),
+  InvalidScope(u32),
+  Decoding(DecodingError),
+}
+
+impl From<EncodingError> for ImageError {
+  fn from(err: EncodingError) -> Self {
+    ImageError::Encoding(err)
+  }
+}
+
+impl From<DecodingError> for ImageError {
+  fn from(err: DecodingError) -> Self {
+    ImageError::Decoding(err)
+  }
+}
+
+impl Display for ImageError {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      ImageError::MachineBusy => write!(fmt, "cannot save an image while a macro is running"),
+      ImageError::Unserializable(elem) => write!(fmt, "`{}` cannot be stored in an image", elem),
+      ImageError::InvalidBody(name) => write!(fmt, "the body of `{}` does not compile", name),
+      ImageError::Encoding(err) => write!(fmt, "{}", err),
+      ImageError::InvalidMagic => write!(fmt, "not a macro image"),
+      ImageError::UnsupportedVersion(version) => write!(fmt, "unsupported image version {}", version),
+      ImageError::Truncated => write!(fmt, "image ends unexpectedly"),
+      ImageError::TrailingBytes(len) => write!(fmt, "{} unexpected byte(s) after the end of the image", len),
+      ImageError::InvalidUtf8 => write!(fmt, "image holds text that is not valid UTF-8"),
+      ImageError::InvalidTag(tag) => write!(fmt, "unknown element tag {}", tag),
+      ImageError::InvalidSymbol(index) => write!(fmt, "symbol index {} is outside the symbol table", index),
+      ImageError::InvalidConstant(index) => write!(fmt, "constant index {} is outside the constant pool", index),
+      ImageError::InvalidElement(text) => write!(fmt, "malformed element `{}`", text),
+      ImageError::TooDeep => write!(fmt, "lists nest more than {} deep", MAX_NESTING),
+      ImageError::InvalidDefinition(elem) => write!(fmt, "invalid definition component `{}`", elem),
+      ImageError::InvalidScope(scope) => write!(fmt, "definition scope {} has no frame", scope),
+      ImageError::Decoding(err) => write!(fmt, "{}", err),
+    }
+  }
+}
+
+impl Error for ImageError {}
+
+impl MetaMachine {
+  // Serializes the definitions, and with `include_stack` the frames too. Without
+  // the stack only the global definitions are kept, since the others belong to
+  // frames that are not.
+  pub fn save_image(&self, include_stack: bool) -> Result<Vec<u8>, ImageError> {
+    if !self.is_idle() {
+      return Err(ImageError::MachineBusy);
+    }
+
+    let mut pool = ConstantPool::default();
+    let mut defs = Writer::default();
+    let compiled = self.defs.compiled()
+      .filter(|(scope, _, _)| include_stack || *scope == 0)
+      .collect::<Vec<_>>();
+    defs.u32(compiled.len());
+    for (scope, (name, form, _), code) in compiled {
+      let code = code.ok_or_else(|| ImageError::InvalidBody(name.clone()))?;
+      defs.u32(scope);
+      defs.u32(pool.symbol(name.as_symbol().unwrap()));
+      defs.u32(pool.add(form)?);
+      defs.u32(code.len());
+      for word in code.code() {
+	match word.constant_index() {
+	  Some(index) => defs.u32(MInstEncoding::constant(pool.add(&code.constants()[index])?)?.word()),
+	  None => defs.u32(word.word()),
+	}
+      }
+    }
+
+    let mut stack = Writer::default();
+    if include_stack {
+      stack.u32(self.stack.len());
+      for frame in self.stack() {
+	stack.u32(frame.len());
+	for elem in frame.iter() {
+	  stack.u32(pool.add(elem)?);
+	}
+      }
+    }
+
+    let mut image = Writer::default();
+    image.bytes(&IMAGE_MAGIC);
+    image.bytes(&IMAGE_VERSION.to_le_bytes());
+    image.u8(if include_stack { FLAG_STACK } else { 0 });
+    image.u8(match self.defs.policy() {
+      RedefinitionPolicy::Error => 0,
+      RedefinitionPolicy::Shadow => 1,
+      RedefinitionPolicy::Replace => 2,
+    });
+    image.u32(pool.names.len());
+    for name in pool.names.iter() {
+      image.str(name.as_str());
+    }
+    image.u32(pool.count);
+    image.bytes(&pool.elements.buf);
+    image.bytes(&defs.buf);
+    image.bytes(&stack.buf);
+    Ok(image.buf)
+  }
+
+  // A machine holding the definitions and frames of an image. Compiled bodies are
+  // used as they are, so the definitions need not be compiled again. Without a
+  // stack in the image the machine starts with an empty root frame.
+  pub fn load_image(image: &[u8]) -> Result<Self, ImageError> {
+    let mut reader = Reader { bytes: image };
+    if reader.take(4)? != IMAGE_MAGIC {
+      return Err(ImageError::InvalidMagic);
+    }
+    let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+    if version != IMAGE_VERSION {
+      return Err(ImageError::UnsupportedVersion(version));
+    }
+    let flags = reader.u8()?;
+    let policy = match reader.u8()? {
+      0 => RedefinitionPolicy::Error,
+      1 => RedefinitionPolicy::Shadow,
+      2 => RedefinitionPolicy::Replace,
+      policy => return Err(ImageError::InvalidTag(policy)),
+    };
+
+    let symbols = (0..reader.u32()?)
+      .map(|_| reader.str().map(Symbol::intern))
+      .collect::<Result<Vec<_>, _>>()?;
+    let constants = (0..reader.u32()?)
+      .map(|_| reader.element(&symbols))
+      .collect::<Result<Vec<_>, _>>()?;
+    let constant = |index: u32| constants.get(index as usize).cloned().ok_or(ImageError::InvalidConstant(index));
+
+    let mut records = vec![];
+    for _ in 0..reader.u32()? {
+      let scope = reader.u32()?;
+      let name = reader.u32()?;
+      let name = symbols.get(name as usize).ok_or(ImageError::InvalidSymbol(name))?;
+      let name = MetaElement::Expr(SymItem::SymAtom(SymAtom::from_symbol(*name)));
+      if name.as_symbol().is_none() {
+	return Err(ImageError::InvalidDefinition(name));
+      }
+      let form = constant(reader.u32()?)?;
+      if form.as_list().is_none() {
+	return Err(ImageError::InvalidDefinition(form));
+      }
+      let words = (0..reader.u32()?).map(|_| reader.u32()).collect::<Result<Vec<_>, _>>()?;
+      records.push((scope, name, form, Bytecode::decode(&words, &constants)?));
+    }
+
+    let mut stack = vec![];
+    if flags & FLAG_STACK != 0 {
+      for _ in 0..reader.u32()? {
+	let frame = (0..reader.u32()?)
+	  .map(|_| reader.u32().and_then(constant))
+	  .collect::<Result<Vec<_>, _>>()?;
+	stack.push(Rc::new(RefCell::new(frame)));
+      }
+    }
+    if stack.is_empty() {
+      stack.push(Rc::new(RefCell::new(vec![])));
+    }
+
+    // Scopes are checked against the frames before anything is defined in them.
+    // Replaying the definitions in order rebuilds shadowed ones too; the saved
+    // policy only applies to what is defined after loading.
+    let mut defs = DefTable::new(RedefinitionPolicy::Shadow);
+    for (scope, name, form, code) in records {
+      if scope as usize >= stack.len() {
+	return Err(ImageError::InvalidScope(scope));
+      }
+      let body = MetaElement::from_elements((0..code.len()).map(|pc| code.fetch(pc)).collect());
+      defs.define_compiled(scope as usize, (name, form, body), Rc::new(code))
+	.map_err(|err| ImageError::InvalidDefinition(err.0))?;
+    }
+    defs.set_policy(policy);
+    if !reader.bytes.is_empty() {
+      return Err(ImageError::TrailingBytes(reader.bytes.len()));
+    }
+
+    let frame = Rc::downgrade(stack.last().unwrap());
+    Ok(MetaMachine {
+      stack,
+      frame,
+      code: vec![],
+      calls: vec![],
+      defs,
+      mode: ExecutionMode::Tree,
+      effects: None,
+    })
+  }
+}
+
+#[derive(Default)]
+struct Writer {
+  buf: Vec<u8>,
+}
+
+impl Writer {
+  fn u8(&mut self, value: u8) {
+    self.buf.push(value);
+  }
+
+  // Counts and indices are bounded by what fits in memory, so a `usize` that does
+  // not fit 32 bits is a bug rather than an error.
+  fn u32(&mut self, value: impl TryInto<u32>) {
+    let value = value.try_into().ok().expect("Image field overflow.");
+    self.buf.extend(value.to_le_bytes());
+  }
+
+  fn bytes(&mut self, bytes: &[u8]) {
+    self.buf.extend(bytes);
+  }
+
+  fn str(&mut self, text: &str) {
+    self.u32(text.len());
+    self.bytes(text.as_bytes());
+  }
+}
+
+// Constants being written, along with the symbol table they refer to. Constants
+// are not shared, so an element reached twice is stored twice.
+#[derive(Default)]
+struct ConstantPool {
+  elements: Writer,
+  count: usize,
+  depth: usize,
+  symbols: HashMap<Symbol, usize>,
+  names: Vec<Symbol>,
+}
+
+impl ConstantPool {
+  fn add(&mut self, elem: &MetaElement) -> Result<usize, ImageError> {
+    self.element(elem)?;
+    self.count += 1;
+    Ok(self.count - 1)
+  }
+
+  fn symbol(&mut self, symbol: Symbol) -> usize {
+    *self.symbols.entry(symbol).or_insert_with(|| {
+      self.names.push(symbol);
+      self.names.len() - 1
+    })
+  }
+
+  fn element(&mut self, elem: &MetaElement) -> Result<(), ImageError> {
+    match elem {
+      MetaElement::Instr(inst, _) => match MInstEncoding::try_from(inst.clone()) {
+	Ok(word) => {
+	  self.elements.u8(TAG_INSTR);
+	  self.elements.u32(word.word());
+	},
+	Err(_) => {
+	  self.elements.u8(TAG_WIDE_INSTR);
+	  self.elements.str(&inst.to_string());
+	},
+      },
+      MetaElement::Expr(sym) => self.item(sym)?,
+    }
+    Ok(())
+  }
+
+  fn item(&mut self, sym: &SymItem) -> Result<(), ImageError> {
+    match sym {
+      SymItem::SymAtom(atom) => {
+	let symbol = self.symbol(atom.symbol());
+	self.elements.u8(TAG_ATOM);
+	self.elements.u32(symbol);
+      },
+      SymItem::SymStr(text) => {
+	self.elements.u8(TAG_STR);
+	self.elements.str(text);
+      },
+      SymItem::SymList(list) => {
+	if self.depth == MAX_NESTING {
+	  return Err(ImageError::TooDeep);
+	}
+	self.depth += 1;
+	self.elements.u8(TAG_LIST);
+	self.elements.u8(match list.delimiter() {
+	  Delimiter::Paren => 0,
+	  Delimiter::Bracket => 1,
+	  Delimiter::Brace => 2,
+	});
+	self.elements.u32(list.len());
+	for item in list.iter() {
+	  self.list_item(item)?;
+	}
+	match list.tail() {
+	  Some(tail) => {
+	    self.elements.u8(1);
+	    self.list_item(tail)?;
+	  },
+	  None => self.elements.u8(0),
+	}
+	self.depth -= 1;
+      },
+      SymItem::SymError(_) => return Err(ImageError::Unserializable(MetaElement::Expr(sym.clone()))),
+    }
+    Ok(())
+  }
+
+  fn list_item(&mut self, item: &SymListItem) -> Result<(), ImageError> {
+    match item {
+      SymListItem::Early(sym) => self.item(sym),
+      SymListItem::Full(elem) => self.element(elem),
+    }
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], ImageError> {
+    if self.bytes.len() < len {
+      return Err(ImageError::Truncated);
+    }
+    let (taken, rest) = self.bytes.split_at(len);
+    self.bytes = rest;
+    Ok(taken)
+  }
+
+  fn u8(&mut self) -> Result<u8, ImageError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<u32, ImageError> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn str(&mut self) -> Result<&'a str, ImageError> {
+    let len = self.u32()?;
+    std::str::from_utf8(self.take(len as usize)?).map_err(|_| ImageError::InvalidUtf8)
+  }
+
+  // Lists are read with a stack of the ones still open rather than by recursion,
+  // so a hostile image fails on the nesting limit instead of overflowing.
+  fn element(&mut self, symbols: &[Symbol]) -> Result<MetaElement, ImageError> {
+    let mut open: Vec<OpenList> = vec![];
+    loop {
+      let mut elem = match self.u8()? {
+	TAG_LIST => {
+	  let delimiter = match self.u8()? {
+	    0 => Delimiter::Paren,
+	    1 => Delimiter::Bracket,
+	    2 => Delimiter::Brace,
+	    delimiter => return Err(ImageError::InvalidTag(delimiter)),
+	  };
+	  if open.len() == MAX_NESTING {
+	    return Err(ImageError::TooDeep);
+	  }
+	  let len = self.u32()?;
+	  open.push(OpenList { delimiter, len, items: vec![], tail: None });
+	  None
+	},
+	tag => Some(self.leaf(tag, symbols)?),
+      };
+      // Hand the element to the innermost open list, and close every list that
+      // it completes.
+      loop {
+	let Some(list) = open.last_mut() else {
+	  return Ok(elem.unwrap());
+	};
+	match (elem.take(), &mut list.tail) {
+	  (Some(elem), Some(tail)) => *tail = Some(elem),
+	  (Some(elem), None) => list.items.push(elem),
+	  (None, _) => {},
+	}
+	match list.tail {
+	  Some(None) => break,
+	  Some(Some(_)) => {},
+	  None if list.items.len() < list.len as usize => break,
+	  None => match self.u8()? {
+	    0 => {},
+	    1 => {
+	      list.tail = Some(None);
+	      break;
+	    },
+	    flag => return Err(ImageError::InvalidTag(flag)),
+	  },
+	}
+	elem = Some(open.pop().unwrap().close()?);
+      }
+    }
+  }
+
+  fn leaf(&mut self, tag: u8, symbols: &[Symbol]) -> Result<MetaElement, ImageError> {
+    match tag {
+      TAG_ATOM => {
+	let index = self.u32()?;
+	let symbol = symbols.get(index as usize).ok_or(ImageError::InvalidSymbol(index))?;
+	Ok(MetaElement::Expr(SymItem::SymAtom(SymAtom::from_symbol(*symbol))))
+      },
+      TAG_STR => Ok(MetaElement::Expr(SymItem::SymStr(SymStr::from_text(self.str()?.to_string())))),
+      TAG_INSTR => {
+	let inst = MacroInstruction::try_from(MInstEncoding::from_word(self.u32()?))?;
+	Ok(MetaElement::Instr(inst, None))
+      },
+      TAG_WIDE_INSTR => {
+	let text = self.str()?;
+	match MetaElement::parse(text) {
+	  Ok(MetaElement::Instr(inst, _)) => Ok(MetaElement::Instr(inst, None)),
+	  _ => Err(ImageError::InvalidElement(text.to_string())),
+	}
+      },
+      tag => Err(ImageError::InvalidTag(tag)),
+    }
+  }
+}
+
+// A list the reader has started but not finished. `tail` is `Some` once the
+// tail flag has been read, and holds the tail once that has been read too.
+struct OpenList {
+  delimiter: Delimiter,
+  len: u32,
+  items: Vec<MetaElement>,
+  tail: Option<Option<MetaElement>>,
+}
+
+impl OpenList {
+  fn close(self) -> Result<MetaElement, ImageError> {
+    let OpenList { delimiter, items, tail, .. } = self;
+    let tail = tail.flatten();
+    // The reader never produces a list tail or a tail without items before it.
+    if tail.as_ref().is_some_and(|tail| items.is_empty() || tail.as_list().is_some()) {
+      let list = MetaElement::from_parts(items, tail);
+      return Err(ImageError::InvalidElement(list.to_string()));
+    }
+    Ok(MetaElement::Expr(SymItem::SymList(SymList::from_parts(items, tail).with_delimiter(delimiter))))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine::tests::{boot, run};
+
+  #[test]
+  fn round_trip() {
+    let library = "
+      (macro (car x) (.SPLIT 1) (.RETURN -2))
+      (macro (car x) (.SPLIT 1) (.SPLIT -2) (.RETURN -2))
+      (macro (pair a b) `(,a . ,b) (.RETURN -1))
+      (macro (data x) '[a {b} . c] \"s\\n\" '(k (.EXPAND 9000)) (.RETURN 2 -1))
+      (pair 1 2)";
+    let program = "(car ((1 2) 3)) (pair p q) (data d) (macro (cdr x) (.SPLIT 1) (.RETURN -1)) (cdr (x . y))";
+    let mut meta = boot();
+    run(&mut meta, library).unwrap();
+
+    let defs = meta.get_defs().cloned().collect::<Vec<_>>();
+    let image = meta.save_image(true).unwrap();
+    let loaded = MetaMachine::load_image(&image).unwrap();
+    assert_eq!(loaded.get_defs().cloned().collect::<Vec<_>>(), defs);
+    assert_eq!(loaded.stack().map(|frame| frame.clone()).collect::<Vec<_>>(),
+	       meta.stack().map(|frame| frame.clone()).collect::<Vec<_>>());
+
+    let expected = run(&mut meta, program).unwrap();
+    assert_eq!(expected, "((1 . 2) 1 (p . q) ([a {b} . c] \"s\\n\" (k (.EXPAND 9000))) y)");
+    for mode in [ExecutionMode::Tree, ExecutionMode::Bytecode] {
+      let mut loaded = MetaMachine::load_image(&image).unwrap();
+      loaded.set_execution_mode(mode);
+      assert_eq!(run(&mut loaded, program), Ok(expected.clone()));
+    }
+    let mut loaded = MetaMachine::load_image(&meta.save_image(false).unwrap()).unwrap();
+    assert_eq!(run(&mut loaded, program), Ok(expected.replacen("(1 . 2) ", "", 1)));
+
+    let mut busy = MetaMachine::new();
+    busy.step().unwrap();
+    assert!(matches!(busy.save_image(false), Err(ImageError::MachineBusy)));
+
+    let deep = (0..MAX_NESTING).fold(MetaElement::from_parts(vec![], None), |elem, _| MetaElement::from_parts(vec![elem], None));
+    assert!(matches!(ConstantPool::default().add(&deep), Err(ImageError::TooDeep)));
+  }
+
+  // An image defining `name` in `scope`, with the body `words` and `()` as the form
+  // and only constant.
+  fn image(names: &[&str], scope: u32, name: u32, words: &[u32]) -> Vec<u8> {
+    let mut image = Writer::default();
+    image.bytes(&IMAGE_MAGIC);
+    image.bytes(&IMAGE_VERSION.to_le_bytes());
+    image.u8(0);
+    image.u8(1);
+    image.u32(names.len());
+    for name in names {
+      image.str(name);
+    }
+    image.u32(1);
+    image.bytes(&[TAG_LIST, 0, 0, 0, 0, 0, 0]);
+    image.u32(1);
+    image.u32(scope);
+    image.u32(name);
+    image.u32(0);
+    image.u32(words.len());
+    for word in words {
+      image.u32(*word);
+    }
+    image.buf
+  }
+
+  // Damaged or hostile images are rejected before anything is built from them.
+  #[test]
+  fn corrupt_images() {
+    let body = [MInstEncoding::try_from(MacroInstruction::Return{range: None}).unwrap().word()];
+    let mut loaded = MetaMachine::load_image(&image(&["f"], 0, 0, &body)).unwrap();
+    assert_eq!(run(&mut loaded, "'x (f)").as_deref(), Ok("(x)"));
+
+    let valid = image(&["f"], 0, 0, &body);
+    let errors = [
+      (&valid[..valid.len() - 1], "image ends unexpectedly"),
+      (&b"SYMA\x01\x00\x00\x01"[..], "not a macro image"),
+      (&b"SYMI\x02\x00\x00\x01"[..], "unsupported image version 2"),
+      (&b"SYMI\x01\x00\x00\x01\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00"[..],
+       "symbol index 0 is outside the symbol table"),
+      (&b"SYMI\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00"[..],
+       "1 unexpected byte(s) after the end of the image"),
+    ];
+    // A constant of lists nested far deeper than any stack would survive.
+    let mut deep = b"SYMI\x01\x00\x00\x01\x00\x00\x00\x00\x01\x00\x00\x00".to_vec();
+    deep.extend([TAG_LIST, 0, 1, 0, 0, 0].repeat(200_000));
+    let errors = errors.into_iter().chain([(&deep[..], "lists nest more than 512 deep")]);
+    for (bytes, message) in errors {
+      assert_eq!(MetaMachine::load_image(bytes).err().map(|err| err.to_string()).as_deref(), Some(message));
+    }
+
+    let cases = [
+      (image(&["f"], 1, 0, &body), "definition scope 1 has no frame"),
+      (image(&["f"], 0x7fffffff, 0, &body), "definition scope 2147483647 has no frame"),
+      (image(&["42"], 0, 0, &body), "invalid definition component `42`"),
+      (image(&["f"], 0, 1, &body), "symbol index 1 is outside the symbol table"),
+      (image(&["f"], 0, 0, &[1 << 3 | 7]), "constant index 1 is outside the constant pool"),
+      (image(&["f"], 0, 0, &[1 << 3]), "instruction word 0x00000008 has malformed arguments"),
+    ];
+    for (bytes, message) in cases {
+      assert_eq!(MetaMachine::load_image(&bytes).err().map(|err| err.to_string()).as_deref(), Some(message));
+    }
+  }
+}
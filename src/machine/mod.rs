@@ -0,0 +1,869 @@
+mod defs;
+mod event;
+mod image;
+
+pub use defs::{MetaDef, RedefinitionPolicy};
+pub use event::{MachineEffect, StepEvent};
+pub use image::{ImageError, IMAGE_MAGIC, IMAGE_VERSION};
+
+use crate::parse::{Span, Symbol, SymItem, QUASIQUOTE, QUOTE, UNQUOTE, UNQUOTE_SPLICING};
+use crate::primitives::{Bytecode, MetaElement, MacroInstruction, Operation, ReturnInstData};
+use defs::{DefTable, RedefinitionError};
+
+use std::cell::{Ref, RefCell};
+use std::fmt::{self, Display};
+use std::rc::{Rc, Weak};
+
+#[derive(Debug)]
+pub enum RuntimeErrorKind {
+  CompoundListAsMacroError(MetaElement),
+  UnknownDef(MetaElement),
+  EmptyMacroCall,
+  ArityMismatch(MetaElement, usize),
+  EmptyFrame,
+  NoSuchFrame(i32),
+  IndexOutOfRange(i64),
+  RangeOutOfBounds(i32, i32),
+  NotAList(MetaElement),
+  InvalidDefinition(MetaElement),
+  Redefinition(MetaElement),
+  InvalidQuote(MetaElement),
+  UnboundName(MetaElement),
+}
+
+impl From<RedefinitionError> for RuntimeErrorKind {
+  fn from(err: RedefinitionError) -> Self {
+    RuntimeErrorKind::Redefinition(err.0)
+  }
+}
+
+impl RuntimeErrorKind {
+  // The element the error is about, when it has one.
+  pub fn element(&self) -> Option<&MetaElement> {
+    match self {
+      RuntimeErrorKind::CompoundListAsMacroError(elem)
+	| RuntimeErrorKind::UnknownDef(elem)
+	| RuntimeErrorKind::ArityMismatch(elem, _)
+	| RuntimeErrorKind::NotAList(elem)
+	| RuntimeErrorKind::InvalidDefinition(elem)
+	| RuntimeErrorKind::Redefinition(elem)
+	| RuntimeErrorKind::InvalidQuote(elem)
+	| RuntimeErrorKind::UnboundName(elem) => Some(elem),
+      _ => None,
+    }
+  }
+}
+
+impl Display for RuntimeErrorKind {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      RuntimeErrorKind::CompoundListAsMacroError(elem) => write!(fmt, "macro name must be a symbol, found `{}`", elem),
+      RuntimeErrorKind::UnknownDef(elem) => write!(fmt, "no definition for `{}`", elem),
+      RuntimeErrorKind::EmptyMacroCall => write!(fmt, "cannot invoke an empty list"),
+      RuntimeErrorKind::ArityMismatch(call, nargs) => write!(fmt, "`{}` needs at least {} argument(s)", call, nargs),
+      RuntimeErrorKind::EmptyFrame => write!(fmt, "active frame is empty"),
+      RuntimeErrorKind::NoSuchFrame(frame) => write!(fmt, "no frame {} on the stack", frame),
+      RuntimeErrorKind::IndexOutOfRange(idx) => write!(fmt, "index {} is out of range", idx),
+      RuntimeErrorKind::RangeOutOfBounds(start, end) => write!(fmt, "range {} {} is out of bounds", start, end),
+      RuntimeErrorKind::NotAList(elem) => write!(fmt, "expected a list, found `{}`", elem),
+      RuntimeErrorKind::InvalidDefinition(elem) => write!(fmt, "invalid definition component `{}`", elem),
+      RuntimeErrorKind::Redefinition(name) => write!(fmt, "`{}` is already defined in this scope", name),
+      RuntimeErrorKind::InvalidQuote(elem) => write!(fmt, "malformed quote form `{}`", elem),
+      RuntimeErrorKind::UnboundName(name) => write!(fmt, "`{}` does not name an argument of the running macro", name),
+    }
+  }
+}
+
+// A runtime error along with the source range it stems from: the offending element
+// when it was read from source, otherwise the instruction being executed. The kind
+// is boxed since it may hold whole elements, and errors travel through every step.
+#[derive(Debug)]
+pub struct RuntimeError {
+  pub kind: Box<RuntimeErrorKind>,
+  pub span: Option<Span>,
+}
+
+impl Display for RuntimeError {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self.span {
+      Some(span) => write!(fmt, "{}: {}", span, self.kind),
+      None => write!(fmt, "{}", self.kind),
+    }
+  }
+}
+
+type StackFrame = RefCell<Vec<MetaElement>>;
+
+// How macro bodies are run: by walking their element trees, or from bytecode
+// compiled the first time each definition is invoked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+  Tree,
+  Bytecode,
+}
+
+// An entry of the code queue: a single element, or what is left of a compiled
+// body from the position given on.
+enum Pending {
+  Elem(MetaElement),
+  Compiled(Rc<Bytecode>, usize),
+}
+
+// A running macro invocation: the length `code` had before its body was queued,
+// the stack depth before its frame was pushed and the arguments bound to the names
+// in its form, for quasiquote templates to unquote.
+struct Call {
+  code_len: usize,
+  depth: usize,
+  bindings: Vec<(Symbol, MetaElement)>,
+}
+
+// The implicitly defined `start` macro is the meta-machine of eval-notes.txt: it
+// takes a macro symbol, form and body and defines them with its single `.DEFINE`.
+// The bootstrap program uses it to define `macro`, whose body splits a
+// `(name args...)` spec into its name and argument form, collects the remaining
+// call arguments as the body and hands the three to `.DEFINE` the same way.
+//
+// The sketch of `macro` in the notes starts with `(.INDEX 0 0)` and stops after
+// splitting the spec. That leaves the name of `macro` itself on the frame and the
+// body uncollected, so `.DEFINE` would take the split name as the form. This body
+// drops the first instruction and collects the body before defining.
+const START_BODY: &str = "((.DEFINE))";
+
+const MACRO_BODY: &str = "
+  (.INDEX 0 1)
+  (.CONTEXT)
+  (.RETURN 0)
+  (.INDEX 0 1)
+  (.CONTEXT)
+  (.RETURN 1 -1)
+  (.INDEX 0)
+  (.CONTEXT)
+  (.RETURN 2 -3)
+  (.DEFINE)";
+
+// Machine layout:
+//   - `stack` holds the frames; the active `frame` is always the top one.
+//   - `code` holds the pending instructions in reverse order, so the next one to
+//     execute is at the end. A compiled body takes a single entry.
+//   - `calls` records every macro invocation still running. Once its body is
+//     consumed, any frames it left behind are dropped.
+//   - `effects` collects what the current instruction did while single-stepping.
+pub struct MetaMachine {
+  stack: Vec<Rc<StackFrame>>,
+  frame: Weak<StackFrame>,
+  code: Vec<Pending>,
+  calls: Vec<Call>,
+  defs: DefTable,
+  mode: ExecutionMode,
+  effects: Option<Vec<MachineEffect>>,
+}
+
+impl MetaMachine {
+  pub fn new() -> Self {
+    let init = MetaElement::parse(format!("(start macro (form body) ({}))", MACRO_BODY.trim()).as_str())
+      .unwrap();
+
+    let stack = vec![Rc::new(RefCell::new(vec![init]))];
+    let initframe = Rc::downgrade(&stack[0]);
+    let initial_def = (MetaElement::parse("start").unwrap(),
+		       MetaElement::parse("(def-form def-body)").unwrap(),
+		       MetaElement::parse(START_BODY).unwrap());
+
+    let mut defs = DefTable::new(RedefinitionPolicy::Shadow);
+    defs.define(0, initial_def).unwrap();
+
+    MetaMachine {
+      stack,
+      frame: initframe,
+      code: vec![],
+      calls: vec![],
+      defs,
+      mode: ExecutionMode::Tree,
+      effects: None,
+    }
+  }
+
+  // Pops the top element of the active frame, executes it and keeps executing
+  // until every instruction it queued has been consumed.
+  pub fn run(&mut self) -> Result<(), RuntimeError> {
+    loop {
+      self.advance()?;
+      if self.is_idle() {
+	return Ok(());
+      }
+    }
+  }
+
+  // Executes exactly one instruction and reports what it did. When the machine is
+  // idle the instruction is the top element of the active frame, as with `run`.
+  pub fn step(&mut self) -> Result<StepEvent, RuntimeError> {
+    self.effects = Some(vec![]);
+    let result = self.advance();
+    let effects = self.effects.take().unwrap();
+    result.map(|executed| StepEvent { executed: executed.unwrap(), effects })
+  }
+
+  // Pushes an element onto the active frame; the next `run` or `step` executes it.
+  pub fn feed(&mut self, elem: MetaElement) {
+    self.active_frame().borrow_mut().push(elem);
+  }
+
+  // True when no macro is running and nothing is queued for execution.
+  pub fn is_idle(&self) -> bool {
+    self.code.is_empty()
+  }
+
+  // Returns the executed element when single-stepping.
+  fn advance(&mut self) -> Result<Option<MetaElement>, RuntimeError> {
+    let (result, span, executed) = match self.code.pop() {
+      Some(Pending::Compiled(code, pc)) => {
+	if pc + 1 < code.len() {
+	  self.code.push(Pending::Compiled(code.clone(), pc + 1));
+	}
+	let executed = self.effects.is_some().then(|| code.fetch(pc));
+	(self.execute_compiled(&code, pc), code.span(pc), executed)
+      },
+      pending => {
+	let elem = match pending {
+	  Some(Pending::Elem(elem)) => elem,
+	  _ => self.pop().map_err(|kind| RuntimeError { kind: Box::new(kind), span: None })?,
+	};
+	let span = elem.span();
+	let executed = self.effects.is_some().then(|| elem.clone());
+	(self.execute(elem), span, executed)
+      },
+    };
+
+    match result {
+      Ok(()) => {
+	self.finish_calls();
+	Ok(executed)
+      },
+      Err(kind) => {
+	self.unwind();
+	let span = kind.element().and_then(|elem| elem.span()).or(span);
+	Err(RuntimeError { kind: Box::new(kind), span })
+      },
+    }
+  }
+
+  // Abandons every running macro, dropping the frames they opened.
+  fn unwind(&mut self) {
+    if let Some(depth) = self.calls.first().map(|call| call.depth) {
+      self.truncate_frames(depth);
+    }
+    self.code.clear();
+    self.calls.clear();
+  }
+
+  fn execute(&mut self, elem: MetaElement) -> Result<(), RuntimeErrorKind> {
+    match elem {
+      MetaElement::Instr(inst, _) => self.execute_instr(inst),
+      // String and number literals evaluate to themselves.
+      MetaElement::Expr(SymItem::SymStr(_)) => self.push(elem),
+      MetaElement::Expr(ref sym) if sym.as_number().is_some() => self.push(elem),
+      MetaElement::Expr(_) => match quote_form(&elem) {
+	Some((QUOTE, quoted)) => self.push(quoted),
+	Some((QUASIQUOTE, template)) => {
+	  let expanded = self.quasiquote(template, 1)?;
+	  self.push(expanded)
+	},
+	Some(_) => Err(RuntimeErrorKind::InvalidQuote(elem)),
+	None => self.invoke(elem),
+      },
+    }
+  }
+
+  // Runs word `pc` of a compiled body, copying out of the constant pool only what
+  // ends up on the stack.
+  fn execute_compiled(&mut self, code: &Bytecode, pc: usize) -> Result<(), RuntimeErrorKind> {
+    let constants = code.constants();
+    match *code.operation(pc) {
+      Operation::Instr(ref inst) => self.execute_instr(inst.clone()),
+      Operation::Literal(index) => self.push(constants[index].clone()),
+      Operation::Quote(index) => match constants[index].as_list().and_then(|list| list.into_iter().nth(1)) {
+	Some(quoted) => self.push(quoted.clone()),
+	None => self.execute(constants[index].clone()),
+      },
+      Operation::Call(name, index) => {
+	let call = &constants[index];
+	self.enter(name, call.elements().unwrap_or_else(|| vec![call.clone()]), call)
+      },
+      Operation::Eval(index) => self.execute(constants[index].clone()),
+    }
+  }
+
+  fn execute_instr(&mut self, inst: MacroInstruction) -> Result<(), RuntimeErrorKind> {
+    match inst {
+      // Without an argument the definition is global. `.DEFINE n` makes it local to
+      // frame n, so it goes out of scope once that frame is popped.
+      MacroInstruction::Define{frame} => {
+	let scope = match frame {
+	  Some(frame) => self.frame_depth(frame)?,
+	  None => 0,
+	};
+	// The name, form and body are checked where they are, so a rejected
+	// definition leaves the frame as it was.
+	{
+	  let frame = self.active_frame();
+	  let elems = frame.borrow();
+	  let [name, form, body] = elems.last_chunk().ok_or(RuntimeErrorKind::EmptyFrame)?;
+	  let symbol = name.as_symbol()
+	    .ok_or_else(|| RuntimeErrorKind::InvalidDefinition(name.clone()))?;
+	  if !form.as_list().is_some_and(|list| list.is_proper()) { Err(RuntimeErrorKind::InvalidDefinition(form.clone()))? }
+	  if !body.as_list().is_some_and(|list| list.is_proper()) { Err(RuntimeErrorKind::InvalidDefinition(body.clone()))? }
+	  if !self.defs.admits(scope, symbol) { Err(RuntimeErrorKind::Redefinition(name.clone()))? }
+	}
+	let body = self.pop()?;
+	let form = self.pop()?;
+	let name = self.pop()?;
+	self.record(|| MachineEffect::Defined(name.clone()));
+	self.defs.define(scope, (name, form, body))?;
+	Ok(())
+      },
+      // Without an argument the top element is consumed; with one, a copy of that
+      // element of the active frame is expanded and the frame is left untouched.
+      MacroInstruction::Expand{narg} => {
+	let elem = match narg {
+	  Some(narg) => Self::element_at(&self.active_frame(), narg)?,
+	  None => self.pop()?,
+	};
+	self.execute(elem)
+      },
+      MacroInstruction::Index{frame, narg} => {
+	let frame = self.frame_at(frame)?;
+	let elem = match narg {
+	  Some(narg) => Self::element_at(&frame, narg)?,
+	  None => MetaElement::from_elements(frame.borrow().clone()),
+	};
+	self.push(elem)
+      },
+      MacroInstruction::Context{range: None} => {
+	let elem = self.pop()?;
+	let elems = elem.elements().ok_or(RuntimeErrorKind::NotAList(elem))?;
+	self.push_frame(elems);
+	Ok(())
+      },
+      // Collapses frames `low` to `high` into a list of frames pushed onto the frame
+      // beneath them, where execution resumes. The `low` frames above the range are
+      // discarded along with it.
+      MacroInstruction::Context{range: Some((start, end))} => {
+	let (low, high) = (start.min(end), start.max(end));
+	if low < 0 { Err(RuntimeErrorKind::NoSuchFrame(low))? }
+	if high as usize >= self.stack.len() { Err(RuntimeErrorKind::NoSuchFrame(high))? }
+	if high as usize + 1 == self.stack.len() { Err(RuntimeErrorKind::NoSuchFrame(high + 1))? }
+
+	let collapsed = self.stack.len() - high as usize - 1;
+	let frames = self.stack[collapsed..].iter().rev()
+	  .skip(low as usize).rev()
+	  .map(|frame| MetaElement::from_elements(frame.take()))
+	  .collect::<Vec<_>>();
+	self.truncate_frames(collapsed);
+	self.push(MetaElement::from_elements(frames))
+      },
+      MacroInstruction::Return{range} => {
+	if self.stack.len() < 2 { Err(RuntimeErrorKind::NoSuchFrame(1))? }
+	let elems = self.pop_frame();
+	match range {
+	  None => Ok(()),
+	  Some(ReturnInstData::Arg(arg)) => {
+	    let elem = elems[Self::element_index(elems.len(), arg)?].clone();
+	    self.push(elem)
+	  },
+	  Some(ReturnInstData::Range(start, end)) => {
+	    let elems = Self::element_range(&elems, start, end)?;
+	    self.push(MetaElement::from_elements(elems))
+	  },
+	}
+      },
+      // Unlike `.INDEX`, `.FRAME n` numbers elements of the active frame, not frames
+      // of the stack: the list at n, or with `.FRAME n arg` the list at arg within
+      // it, is popped off the active frame's place on the stack and pushed in its
+      // stead, closing the scopes of the frame it replaces.
+      MacroInstruction::Frame{frame, narg} => {
+	if self.stack.len() < 2 { Err(RuntimeErrorKind::NoSuchFrame(1))? }
+	let mut selected = Self::element_at(&self.active_frame(), frame)?;
+	if let Some(narg) = narg {
+	  let inner = selected.as_list().filter(|list| list.is_proper())
+	    .ok_or_else(|| RuntimeErrorKind::NotAList(selected.clone()))?;
+	  let idx = Self::element_index(inner.len(), narg)?;
+	  selected = inner.into_iter().nth(idx).unwrap().clone();
+	}
+	let elems = selected.elements().ok_or(RuntimeErrorKind::NotAList(selected))?;
+	self.pop_frame();
+	self.push_frame(elems);
+	Ok(())
+      },
+      // Pushes the head and then the tail of element n, which works on improper
+      // lists too: `(a b . c)` splits into `a` and `(b . c)`, `(a . b)` into `a`
+      // and `b`, and `(a)` into `a` and `()`.
+      MacroInstruction::Split{narg} => {
+	let selected = Self::element_at(&self.active_frame(), narg)?;
+	let list = selected.as_list().ok_or_else(|| RuntimeErrorKind::NotAList(selected.clone()))?;
+	let (head, tail) = list.head().cloned().zip(list.tail())
+	  .ok_or_else(|| RuntimeErrorKind::NotAList(selected.clone()))?;
+	self.push(head)?;
+	self.push(tail)
+      },
+    }
+  }
+
+  // A macro call opens a new frame holding the whole call, so the macro symbol is
+  // element 0 and its arguments follow it. Calls may supply more arguments than the
+  // form names; the extra ones are left in the frame for the body to pick up.
+  fn invoke(&mut self, call: MetaElement) -> Result<(), RuntimeErrorKind> {
+    if call.as_list().is_some_and(|list| !list.is_proper()) {
+      return Err(RuntimeErrorKind::NotAList(call));
+    }
+    let frame = call.elements().unwrap_or_else(|| vec![call.clone()]);
+    let macro_symbol = frame.first().ok_or(RuntimeErrorKind::EmptyMacroCall)?;
+    let macro_name = macro_symbol.as_symbol()
+      .ok_or_else(|| RuntimeErrorKind::CompoundListAsMacroError(macro_symbol.clone()))?;
+    self.enter(macro_name, frame, &call)
+  }
+
+  // Queues the body of `macro_name` and opens `frame`, the elements of `call`.
+  fn enter(&mut self, macro_name: Symbol, frame: Vec<MetaElement>, call: &MetaElement) -> Result<(), RuntimeErrorKind> {
+    let (_, form, body) = self.get_def(macro_name)
+      .ok_or_else(|| RuntimeErrorKind::UnknownDef(frame[0].clone()))?;
+
+    let nargs = form.as_list().map_or(0, |form| form.len());
+    if frame.len() - 1 < nargs {
+      Err(RuntimeErrorKind::ArityMismatch(call.clone(), nargs))?
+    }
+
+    let bindings = form.elements().unwrap_or_default().iter().zip(frame.iter().skip(1))
+      .filter_map(|(param, arg)| Some((param.as_symbol()?, arg.clone())))
+      .collect();
+    let compiled = match self.mode {
+      ExecutionMode::Bytecode => self.defs.bytecode(macro_name).filter(|code| !code.is_empty()),
+      ExecutionMode::Tree => None,
+    };
+    let body = match compiled {
+      Some(code) => vec![Pending::Compiled(code, 0)],
+      None => body.elements().ok_or_else(|| RuntimeErrorKind::NotAList(body.clone()))?
+	.into_iter().rev().map(Pending::Elem).collect(),
+    };
+    self.calls.push(Call { code_len: self.code.len(), depth: self.stack.len(), bindings });
+    self.code.extend(body);
+    self.push_frame(frame);
+    Ok(())
+  }
+
+  // Copies `template`, replacing `(unquote x)` with the value of `x` and splicing
+  // the elements of `(unquote-splicing x)` into the enclosing list. `x` is either
+  // the name of an argument of the running macro or an index into the active
+  // frame. Unquotes inside a nested quasiquote belong to it and are kept.
+  fn quasiquote(&self, template: MetaElement, level: usize) -> Result<MetaElement, RuntimeErrorKind> {
+    let list = match template.as_list() {
+      Some(list) => list,
+      None => return Ok(template),
+    };
+    let items = list.into_iter().cloned().collect::<Vec<_>>();
+
+    let nested_level = match quote_form(&template) {
+      Some((UNQUOTE, operand)) if level == 1 => return self.unquote(&template, operand),
+      Some((UNQUOTE_SPLICING, _)) if level == 1 => return Err(RuntimeErrorKind::InvalidQuote(template)),
+      Some((QUASIQUOTE, _)) => level + 1,
+      Some((UNQUOTE, _)) | Some((UNQUOTE_SPLICING, _)) => level - 1,
+      _ => level,
+    };
+
+    let mut expanded = vec![];
+    let mut tail = match list.dotted_tail() {
+      Some(tail) => Some(self.quasiquote(tail.clone(), nested_level)?),
+      None => None,
+    };
+    for (idx, item) in items.iter().enumerate() {
+      // `(a . ,b)` reads as `(a unquote b)`, so an unquote in tail position shows up
+      // as its two parts at the end of the list.
+      if idx > 0 && tail.is_none() && nested_level == 1 && items.len() - idx == 2 {
+	let rest = MetaElement::from_elements(items[idx..].to_vec());
+	if let Some((UNQUOTE, operand)) = quote_form(&rest) {
+	  tail = Some(self.unquote(&rest, operand)?);
+	  break;
+	}
+      }
+      match quote_form(item) {
+	Some((UNQUOTE_SPLICING, operand)) if nested_level == 1 => {
+	  let spliced = self.unquote(item, operand)?;
+	  expanded.extend(spliced.elements().ok_or(RuntimeErrorKind::NotAList(spliced))?);
+	},
+	_ => expanded.push(self.quasiquote(item.clone(), nested_level)?),
+      }
+    }
+    Ok(MetaElement::from_parts(expanded, tail))
+  }
+
+  fn unquote(&self, form: &MetaElement, operand: MetaElement) -> Result<MetaElement, RuntimeErrorKind> {
+    if let Some(idx) = operand.as_number().and_then(|number| number.as_integer()) {
+      let frame = self.active_frame();
+      let elems = frame.borrow();
+      return Ok(elems[Self::element_index(elems.len(), idx)?].clone());
+    }
+
+    let name = operand.as_symbol().ok_or_else(|| RuntimeErrorKind::InvalidQuote(form.clone()))?;
+    self.calls.last()
+      .and_then(|call| call.bindings.iter().find(|(param, _)| *param == name))
+      .map(|(_, arg)| arg.clone())
+      .ok_or(RuntimeErrorKind::UnboundName(operand))
+  }
+
+  fn finish_calls(&mut self) {
+    while let Some(call) = self.calls.last() {
+      if self.code.len() > call.code_len {
+	break;
+      }
+      let depth = call.depth;
+      self.calls.pop();
+      self.truncate_frames(depth);
+    }
+  }
+
+  fn record(&mut self, effect: impl FnOnce() -> MachineEffect) {
+    if let Some(effects) = self.effects.as_mut() {
+      effects.push(effect());
+    }
+  }
+
+  fn active_frame(&self) -> Rc<StackFrame> {
+    self.frame.upgrade().expect("Active frame dropped from the stack.")
+  }
+
+  // Frames are numbered from the active one: 0 is the active frame, 1 the frame
+  // beneath it, and so on.
+  fn frame_at(&self, frame: i32) -> Result<Rc<StackFrame>, RuntimeErrorKind> {
+    Ok(self.stack[self.frame_depth(frame)?].clone())
+  }
+
+  // Stack index of a frame numbered from the active one.
+  fn frame_depth(&self, frame: i32) -> Result<usize, RuntimeErrorKind> {
+    usize::try_from(frame).ok()
+      .and_then(|frame| (self.stack.len() - 1).checked_sub(frame))
+      .ok_or(RuntimeErrorKind::NoSuchFrame(frame))
+  }
+
+  fn push(&mut self, elem: MetaElement) -> Result<(), RuntimeErrorKind> {
+    self.record(|| MachineEffect::ElementPushed(elem.clone()));
+    self.active_frame().borrow_mut().push(elem);
+    Ok(())
+  }
+
+  fn pop(&mut self) -> Result<MetaElement, RuntimeErrorKind> {
+    let elem = self.active_frame().borrow_mut().pop().ok_or(RuntimeErrorKind::EmptyFrame)?;
+    self.record(|| MachineEffect::ElementPopped(elem.clone()));
+    Ok(elem)
+  }
+
+  fn push_frame(&mut self, elems: Vec<MetaElement>) {
+    self.stack.push(Rc::new(RefCell::new(elems)));
+    self.frame = Rc::downgrade(self.stack.last().unwrap());
+    let depth = self.stack.len() - 1;
+    self.record(|| MachineEffect::FramePushed(depth));
+  }
+
+  fn pop_frame(&mut self) -> Vec<MetaElement> {
+    let frame = self.stack.last().unwrap().take();
+    self.truncate_frames(self.stack.len() - 1);
+    frame
+  }
+
+  // Pops every frame at stack index `depth` or above, along with their scopes.
+  fn truncate_frames(&mut self, depth: usize) {
+    for popped in (depth..self.stack.len()).rev() {
+      self.record(|| MachineEffect::FramePopped(popped));
+    }
+    self.stack.truncate(depth);
+    self.defs.close_scopes(depth);
+    self.frame = Rc::downgrade(self.stack.last().unwrap());
+  }
+
+  // A copy of element `idx` of `frame`, leaving the rest of it uncopied.
+  fn element_at(frame: &StackFrame, idx: i32) -> Result<MetaElement, RuntimeErrorKind> {
+    let elems = frame.borrow();
+    Ok(elems[Self::element_index(elems.len(), idx)?].clone())
+  }
+
+  // Negative indices count back from the end of the frame, -1 being the last element.
+  fn element_index(len: usize, idx: impl Into<i64>) -> Result<usize, RuntimeErrorKind> {
+    let idx = idx.into();
+    let resolved = if idx < 0 { len as i64 + idx } else { idx };
+    if resolved < 0 || resolved >= len as i64 {
+      Err(RuntimeErrorKind::IndexOutOfRange(idx))
+    }
+    else {
+      Ok(resolved as usize)
+    }
+  }
+
+  // Inclusive range of elements; an end before the start selects nothing, so
+  // `(.RETURN 1 -1)` on a one element frame yields `()`.
+  fn element_range(elems: &[MetaElement], start: i32, end: i32) -> Result<Vec<MetaElement>, RuntimeErrorKind> {
+    let len = elems.len() as i64;
+    let resolve = |idx: i32| if idx < 0 { len + idx as i64 } else { idx as i64 };
+    let (first, last) = (resolve(start), resolve(end));
+    if first < 0 || first > len || last < -1 || last >= len {
+      Err(RuntimeErrorKind::RangeOutOfBounds(start, end))
+    }
+    else {
+      Ok(elems[first as usize..(last + 1).max(first) as usize].to_vec())
+    }
+  }
+
+  pub fn get_defs(&self) -> impl Iterator<Item = &MetaDef> {
+    self.defs.iter()
+  }
+
+  pub fn get_def(&self, name: Symbol) -> Option<&MetaDef> {
+    self.defs.lookup(name)
+  }
+
+  // Frames from the root (index 0) up to the active one.
+  pub fn stack(&self) -> impl Iterator<Item = Ref<'_, Vec<MetaElement>>> {
+    self.stack.iter().map(|frame| frame.borrow())
+  }
+
+  pub fn frame(&self) -> Ref<'_, Vec<MetaElement>> {
+    self.stack.last().unwrap().borrow()
+  }
+
+  // Instructions still queued, next one first.
+  pub fn pending(&self) -> impl Iterator<Item = MetaElement> + '_ {
+    self.code.iter().rev().flat_map(|pending| match pending {
+      Pending::Elem(elem) => vec![elem.clone()],
+      Pending::Compiled(code, pc) => (*pc..code.len()).map(|pc| code.fetch(pc)).collect(),
+    })
+  }
+
+  pub fn execution_mode(&self) -> ExecutionMode {
+    self.mode
+  }
+
+  pub fn set_execution_mode(&mut self, mode: ExecutionMode) {
+    self.mode = mode;
+  }
+
+  pub fn redefinition_policy(&self) -> RedefinitionPolicy {
+    self.defs.policy()
+  }
+
+  pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+    self.defs.set_policy(policy);
+  }
+
+  pub fn print_def(def: &MetaDef) {
+    let def_string = format!("{}", def.2);
+    println!("{} {}\n{}", def.0, def.1, def_string);
+  }
+}
+
+impl Default for MetaMachine {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Splits a `(quote x)`, `(quasiquote x)`, `(unquote x)` or `(unquote-splicing x)`
+// form into its name and operand.
+fn quote_form(elem: &MetaElement) -> Option<(Symbol, MetaElement)> {
+  let mut items = elem.elements()?;
+  if items.len() != 2 {
+    return None;
+  }
+  let name = items[0].as_symbol().filter(|name| [QUOTE, QUASIQUOTE, UNQUOTE, UNQUOTE_SPLICING].contains(name))?;
+  Some((name, items.pop().unwrap()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::parse::ProgramReader;
+
+  pub(crate) fn boot() -> MetaMachine {
+    let mut meta = MetaMachine::new();
+    meta.run().unwrap();
+    meta
+  }
+
+  // Runs every form of `program` and returns the active frame, or the message of
+  // the first error.
+  pub(crate) fn run(meta: &mut MetaMachine, program: &str) -> Result<String, String> {
+    for item in ProgramReader::new(program) {
+      meta.feed(MetaElement::try_from(&item.unwrap()).unwrap());
+      meta.run().map_err(|err| err.kind.to_string())?;
+    }
+    let frame = meta.frame().clone();
+    Ok(MetaElement::from_elements(frame).to_string())
+  }
+
+  #[test]
+  fn calls_and_returns() {
+    let mut meta = boot();
+    let program = "
+      (macro (id x) (.RETURN 1))
+      (macro (drop x) (.RETURN))
+      (macro (rest x y z) (.RETURN 2 -1))
+      (macro (nothing x) 'ignored)
+      (macro (twice x) (id x) (id y) (.RETURN -2 -1))
+      (id a) (drop b) (rest a b c) (nothing d) (twice q)";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("(a (b c) (x y))"));
+    assert_eq!(meta.stack().count(), 1);
+    assert!(meta.is_idle());
+  }
+
+  #[test]
+  fn bootstrap() {
+    let mut meta = boot();
+    let start = meta.get_def(Symbol::intern("start")).unwrap().2.to_string();
+    assert_eq!(start, "((.DEFINE))");
+    let program = "(start both (a b) ((.RETURN 1 2))) (both x y) (macro (first a b) (.RETURN 1)) (first x y)";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("((x y) x)"));
+  }
+
+  // `(.EXPAND)` consumes the top element; `(.EXPAND n)` runs a copy and leaves the
+  // frame as it was.
+  #[test]
+  fn expand() {
+    let mut meta = boot();
+    run(&mut meta, "(macro (id x) (.RETURN 1))").unwrap();
+    assert_eq!(run(&mut meta, "'(id a) (.EXPAND)").as_deref(), Ok("(a)"));
+    assert_eq!(run(&mut meta, "'(id b) (.EXPAND 1)").as_deref(), Ok("(a (id b) b)"));
+    assert_eq!(run(&mut meta, "(.EXPAND -2)").as_deref(), Ok("(a (id b) b b)"));
+    assert_eq!(run(&mut meta, "(.EXPAND 4)"), Err("index 4 is out of range".to_string()));
+    assert_eq!(run(&mut meta, "(.EXPAND -5)"), Err("index -5 is out of range".to_string()));
+    assert_eq!(run(&mut meta, "(.EXPAND)"), Err("no definition for `b`".to_string()));
+  }
+
+  // The first `if` of eval-notes.txt expands the top element, `else`, then the
+  // condition, so only `then` is left unexpanded until the condition picks it.
+  // The booleans answer for `if`: `true` expands `then` and `false` keeps the
+  // expanded `else`, each returning through the frame of `if` as well. The
+  // second `if` shadows the first, and calls a macro named `cond` since only
+  // quasiquote templates see arguments by name.
+  #[test]
+  fn eval_notes_if() {
+    let notes = include_str!("../../eval-notes.txt");
+    let start = notes.find("(macro (if").unwrap();
+    let second = start + 1 + notes[start + 1..].find("(macro (if").unwrap();
+    let booleans = "
+      (macro (true) (.INDEX 1 2) (.EXPAND) (.RETURN -1) (.RETURN -1))
+      (macro (false) (.RETURN) (.RETURN -1))";
+    for mode in [ExecutionMode::Tree, ExecutionMode::Bytecode] {
+      let mut meta = boot();
+      meta.set_execution_mode(mode);
+      run(&mut meta, booleans).unwrap();
+      assert_eq!(run(&mut meta, &notes[start..second]).as_deref(), Ok("()"));
+      assert_eq!(run(&mut meta, "(if true 'hi 'bye) (if false 'hi 'bye)").as_deref(), Ok("(hi bye)"));
+      assert_eq!(run(&mut meta, "(if false (undefined) 'bye)").as_deref(), Ok("(hi bye bye)"));
+      assert_eq!(run(&mut meta, &notes[second..notes.find("(macro (car").unwrap()]).as_deref(), Ok("(hi bye bye)"));
+      assert_eq!(run(&mut meta, "(if true 'hi 'bye)"), Err("no definition for `cond`".to_string()));
+    }
+  }
+
+  // The `car` and `cdr` examples of eval-notes.txt load and run as written.
+  #[test]
+  fn eval_notes() {
+    let notes = include_str!("../../eval-notes.txt");
+    let examples = &notes[notes.find("(macro (car").unwrap()..notes.find("(macro (scopy").unwrap()];
+    let mut meta = boot();
+    assert_eq!(run(&mut meta, examples).as_deref(), Ok("()"));
+    let program = "(car (a b c)) (cdr (a b c))";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("(a (b c))"));
+    let program = "(macro (head-of-second x) (.FRAME 1 1) (.RETURN 0)) (head-of-second ((a b) (c d)))";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("(a (b c) c)"));
+    assert_eq!(run(&mut meta, "(head-of-second ((a b)))"), Err("index 1 is out of range".to_string()));
+    assert_eq!(run(&mut meta, "(.FRAME 0)"), Err("no frame 1 on the stack".to_string()));
+
+    meta.feed(MetaElement::parse("(car (a b c))").unwrap());
+    meta.step().unwrap();
+    let event = meta.step().unwrap();
+    assert_eq!(event.executed.to_string(), "(.FRAME 1)");
+    assert_eq!((event.frames_pushed(), event.frames_popped()), (1, 1));
+    assert_eq!(meta.stack().map(|frame| frame.len()).collect::<Vec<_>>(), [3, 3]);
+  }
+
+  // Unquotes take the running macro's arguments by name or the active frame's
+  // elements by index; splices and dotted tails merge lists, and a nested
+  // quasiquote keeps its own unquotes.
+  #[test]
+  fn quasiquote() {
+    let mut meta = boot();
+    let program = "
+      (macro (wrap x y) `(,x ,@y (,-1 . ,x) `(a ,x) . ,y) (.RETURN -1))
+      (wrap p (q r))";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("((p q r ((q r) . p) (quasiquote (a (unquote x))) q r))"));
+
+    let cases = [
+      ("(macro (f x) `(,z)) (f a)", "`z` does not name an argument of the running macro"),
+      ("(macro (f x) `(,@x)) (f a)", "expected a list, found `a`"),
+      ("(macro (f x) `,@x) (f (a))", "malformed quote form `(unquote-splicing x)`"),
+      ("(macro (f x) `(,(x))) (f a)", "malformed quote form `(unquote (x))`"),
+      ("(macro (f x) `(,3)) (f a)", "index 3 is out of range"),
+      ("(macro (f x) `(,99999999999)) (f a)", "index 99999999999 is out of range"),
+      ("(macro (f x) `(,-9223372036854775808)) (f a)", "index -9223372036854775808 is out of range"),
+    ];
+    for (program, message) in cases {
+      assert_eq!(run(&mut boot(), program), Err(message.to_string()), "program {:?}", program);
+    }
+  }
+
+  #[test]
+  fn runtime_errors() {
+    let cases = [
+      ("(undefined a)", "no definition for `undefined`"),
+      ("()", "cannot invoke an empty list"),
+      ("((a) b)", "macro name must be a symbol, found `(a)`"),
+      ("(macro (two a b) (.RETURN 1)) (two x)", "`(two x)` needs at least 2 argument(s)"),
+      ("(.RETURN)", "no frame 1 on the stack"),
+      ("(.CONTEXT 0 0)", "no frame 1 on the stack"),
+      ("(.CONTEXT -1 0)", "no frame -1 on the stack"),
+      ("(.CONTEXT 0 2147483647)", "no frame 2147483647 on the stack"),
+      ("(macro (bad x) (.SPLIT 1)) (bad a)", "expected a list, found `a`"),
+      ("(start f (x) ((.RETURN 1) . y)) (f a)", "invalid definition component `((.RETURN 1) . y)`"),
+      ("(start f (x . y) ((.RETURN 1))) (f a)", "invalid definition component `(x . y)`"),
+    ];
+    for (program, message) in cases {
+      assert_eq!(run(&mut boot(), program), Err(message.to_string()), "program {:?}", program);
+    }
+
+    // A rejected definition leaves its parts on the frame.
+    let mut meta = boot();
+    assert_eq!(run(&mut meta, "'f '(x) 'body (.DEFINE)"), Err("invalid definition component `body`".to_string()));
+    assert_eq!(MetaElement::from_elements(meta.frame().clone()).to_string(), "(f (x) body)");
+    meta.set_redefinition_policy(RedefinitionPolicy::Error);
+    run(&mut meta, "(macro (id x) (.RETURN 1))").unwrap();
+    assert_eq!(run(&mut meta, "'id '(y) '((.RETURN 1)) (.DEFINE)"), Err("`id` is already defined in this scope".to_string()));
+    assert_eq!(MetaElement::from_elements(meta.frame().clone()).to_string(), "(f (x) body id (y) ((.RETURN 1)))");
+  }
+
+  // A failing call deep inside others drops every frame they opened, and the
+  // machine keeps working afterwards.
+  #[test]
+  fn unwinding() {
+    let mut meta = boot();
+    let program = "
+      (macro (bad x) (.SPLIT 1))
+      (macro (middle x) (bad x) (.RETURN -1))
+      (macro (outer x) (middle x) (.RETURN -1))
+      'kept (outer a)";
+    assert_eq!(run(&mut meta, program), Err("expected a list, found `x`".to_string()));
+    assert_eq!(meta.stack().count(), 1);
+    assert!(meta.is_idle());
+    assert_eq!(run(&mut meta, "(macro (id x) (.RETURN 1)) (id b)").as_deref(), Ok("(kept b)"));
+  }
+
+  #[test]
+  fn context_range() {
+    let mut meta = boot();
+    let program = "
+      (macro (inner y) (.CONTEXT 0 0))
+      (macro (outer x) (inner y) (.RETURN -1))
+      (outer a)
+      (macro (inner2 y) 'gone (.CONTEXT 1 1))
+      (macro (outer2 x) (inner2 y))
+      (outer2 b)";
+    assert_eq!(run(&mut meta, program).as_deref(), Ok("(((inner y)) ((outer2 b)))"));
+    assert_eq!(meta.stack().count(), 1);
+  }
+}
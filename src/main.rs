@@ -1,120 +1,173 @@
-#![feature(iter_advance_by)]
-#![feature(iterator_try_collect)]
-#![feature(never_type)]
-
-mod parse;
-mod primitives;
-
-use crate::primitives::MetaElement;
-
-use std::cell::RefCell;
-use std::rc::{Rc, Weak};
-use std::slice::Iter;
-
-#[derive(Debug)]
-enum RuntimeError<'a> {
-  CompoundListAsMacroError(&'a MetaElement),
-  UnknownDef(&'a MetaElement),
+mod repl;
+
+use syms::diagnostics::{Diagnose, Diagnostic};
+use syms::machine::{ExecutionMode, MetaMachine};
+use syms::parse::{parse_recovering, ParseError, ProgramReader};
+use syms::primitives::MetaElement;
+
+use crate::repl::Repl;
+
+use std::fs;
+use std::io::{self, Read};
+
+// Executes every top-level form of `source` in order against `meta`, stopping at
+// the first one that fails to parse or run.
+pub fn run_source(meta: &mut MetaMachine, source: &str) -> Result<(), Diagnostic> {
+  for item in ProgramReader::new(source) {
+    let elem = item.map_err(ParseError::from)
+      .and_then(|item| Ok(MetaElement::try_from(&item)?))
+      .map_err(|err| err.diagnose())?;
+    meta.feed(elem);
+    meta.run().map_err(|err| err.diagnose())?;
+  }
+  Ok(())
 }
 
-type MetaDef = (MetaElement, MetaElement, MetaElement);
-type StackFrame = RefCell<Vec<MetaElement>>;
-
-struct MetaMachine {
-  stack: Vec<Rc<StackFrame>>,
-  frame: Weak<StackFrame>,
-  code: Vec<MetaElement>,
-  defs: Vec<MetaDef>,
+fn read_source_or_exit(path: &str) -> String {
+  read_source(path).unwrap_or_else(|err| {
+    eprintln!("Cannot read {}: {}", path, err);
+    std::process::exit(1);
+  })
 }
 
-impl MetaMachine {
-  fn new() -> Self {
-    let init = MetaElement::parse("
-      (start (macro form body)
-        (.INDEX 0 0)
-        (.INDEX 0 1)
-        (.CONTEXT)
-        (.RETURN 0)
-        (.INDEX 0 1)
-        (.CONTEXT)
-        (.RETURN 1 -1)
-        (.DEFINE))")
-      .unwrap();
-
-    let stack = vec![Rc::new(RefCell::new(vec![init]))];
-    let initframe = Rc::downgrade(&stack[0]);
-    let initial_def = (MetaElement::parse("start").unwrap(),
-		       MetaElement::parse("(def-form def-body)").unwrap(),
-		       MetaElement::parse("((.DEFINE))").unwrap());
-
-    MetaMachine {
-      stack : stack,
-      frame: initframe,
-      code: vec![],
-      defs : vec![initial_def],
-    }
+fn read_source(path: &str) -> io::Result<String> {
+  if path == "-" {
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+    Ok(source)
+  }
+  else {
+    fs::read_to_string(path)
   }
+}
+
+fn load_image_or_exit(path: &str) -> MetaMachine {
+  let image = fs::read(path).unwrap_or_else(|err| {
+    eprintln!("Cannot read {}: {}", path, err);
+    std::process::exit(1);
+  });
+  MetaMachine::load_image(&image).unwrap_or_else(|err| {
+    eprint!("{}", err.diagnose().render(path, None));
+    std::process::exit(1);
+  })
+}
 
-  fn run(&mut self) -> Result<(), RuntimeError> {
-    // let exec = &self.frame.upgrade().unwrap()
-    //   .borrow().iter().rev().nth(0).unwrap();
-    // self.code = exec;
-
-    // while self.code.len() != 0
-    // match exec {
-    //   MetaElement::Expr(_) => {
-    // 	// let macro_symbol = exec.as_list().unwrap().into_iter().nth(0).unwrap();
-    // 	// let macro_name = macro_symbol.as_str()
-    // 	//   .ok_or(RuntimeError::CompoundListAsMacroError(&macro_symbol))?;
-    // 	// let macro_def = self.get_def(macro_symbol)
-    // 	//   .ok_or(RuntimeError::UnknownDef(&macro_symbol))?;
-
-    // 	// let nargs = macro_def.1.as_list().unwrap().len();
-    // 	// let (rem_stack, args) = {
-    // 	//   let rev_stack = self.frame.upgrade().unwrap()
-    // 	//     .borrow().iter().rev().skip(1);
-    // 	//   let args = rev_stack.take(nargs).collect::<Vec<_>>();
-    // 	//   let rem_stack = rev_stack.rev().collect::<Vec<_>>();
-    // 	//   (rem_stack, args)
-    // 	// };
-    // 	// self.frame.upgrade().unwrap().replace(rem_stack);
-    // 	()
-    //   },
-    //   MetaElement::Instr(instr) => {
-    // 	()
-    //   },
-    // };
-
-    // println!("{}", exec);
-    Ok(())
+fn boot() -> MetaMachine {
+  let mut meta = MetaMachine::new();
+  if let Err(err) = meta.run() {
+    eprint!("{}", err.diagnose().render("<bootstrap>", None));
+    std::process::exit(1);
   }
+  meta
+}
 
-  fn get_defs(&self) -> &Vec<MetaDef> {
-    &self.defs
+fn main() {
+  let args = std::env::args().collect::<Vec<_>>();
+  match args.get(1).map(|arg| arg.as_str()) {
+    None | Some("repl") => {
+      Repl::new().run().expect("Failed to read input");
+    },
+    Some("run") => {
+      let (mut bytecode, mut image, mut path) = (false, None, "-");
+      let mut rest = args[2..].iter();
+      while let Some(arg) = rest.next() {
+	match arg.as_str() {
+	  "--bytecode" => bytecode = true,
+	  // Starts from the definitions of a saved image instead of the bootstrap.
+	  "--image" => image = rest.next(),
+	  arg => path = arg,
+	}
+      }
+      let source = read_source_or_exit(path);
+
+      let mut meta = image.map_or_else(boot, |image| load_image_or_exit(image));
+      if bytecode {
+	meta.set_execution_mode(ExecutionMode::Bytecode);
+      }
+      if let Err(err) = run_source(&mut meta, &source) {
+	eprint!("{}", err.render(path, Some(&source)));
+	std::process::exit(1);
+      }
+      for elem in meta.frame().iter() {
+	println!("{}", elem);
+      }
+    },
+    // Reports every syntax error in a file instead of just the first.
+    Some("check") => {
+      let path = args.get(2).map_or("-", |path| path.as_str());
+      let source = read_source_or_exit(path);
+      let (_, errors) = parse_recovering(&source);
+      for err in errors.iter() {
+	eprint!("{}", err.diagnose().render(path, Some(&source)));
+      }
+      if !errors.is_empty() {
+	std::process::exit(1);
+      }
+    },
+    // Runs a file and saves the definitions it leaves behind, for `run --image`.
+    Some("image") => {
+      let include_stack = args.get(2).is_some_and(|arg| arg == "--stack");
+      let paths = &args[if include_stack { 3 } else { 2 }..];
+      let (path, out) = match paths {
+	[path, out] => (path.as_str(), out.as_str()),
+	_ => {
+	  eprintln!("usage: {} image [--stack] <file> <out>", args[0]);
+	  std::process::exit(2);
+	},
+      };
+      let source = read_source_or_exit(path);
+
+      let mut meta = boot();
+      if let Err(err) = run_source(&mut meta, &source) {
+	eprint!("{}", err.render(path, Some(&source)));
+	std::process::exit(1);
+      }
+      let image = meta.save_image(include_stack).unwrap_or_else(|err| {
+	eprint!("{}", err.diagnose().render(path, Some(&source)));
+	std::process::exit(1);
+      });
+      if let Err(err) = fs::write(out, image) {
+	eprintln!("Cannot write {}: {}", out, err);
+	std::process::exit(1);
+      }
+    },
+    Some("defs") => {
+      let meta = boot();
+      for def in meta.get_defs() {
+	MetaMachine::print_def(def);
+      }
+    },
+    Some(command) => {
+      eprintln!("Unknown command {}; usage: {} [repl | run [--bytecode] [--image <image>] [file] | image [--stack] <file> <out> | check [file] | defs]", command, args[0]);
+      std::process::exit(2);
+    },
   }
+}
 
-  // fn get_def(&self, sym: &MetaElement) -> Option<MetaDef> {
-  //   self.defs.iter().find(|def| {
-  //     def.0 == sym
-  //   })
-  // }
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-  fn print_def(def: &MetaDef) {
-    let def_string = format!("{}", def.2);
-    println!("{} {}\n{}", def.0, def.1, def_string);
+  fn frame(meta: &MetaMachine) -> String {
+    MetaElement::from_elements(meta.frame().clone()).to_string()
   }
-}
 
-fn main() {
-  let mut meta = MetaMachine::new();
-  meta.run().expect("Runtime error");
-  // for def in meta.get_defs() {
-  //   MetaMachine::print_def(def);
-  // }
-  // for scope in meta.stack {
-  //   for arg in scope {
-  //     println!("{}", arg.as_list().unwrap().len());
-  //     println!("{}", &arg);
-  //   }
-  // }
+  // Forms run in order against one machine, and the first failure stops the program.
+  #[test]
+  fn program() {
+    let mut meta = boot();
+    run_source(&mut meta, "; ids\n(macro (id x) (.RETURN 1))\n(id a)\n(id b)\n").unwrap();
+    assert_eq!(frame(&meta), "(a b)");
+
+    let mut meta = boot();
+    let err = run_source(&mut meta, "(macro (id x) (.RETURN 1))\n(id a)\n(nope)\n(id b)").unwrap_err();
+    assert_eq!(err.span.map(|span| span.start.line), Some(3));
+    assert_eq!(frame(&meta), "(a)");
+
+    let mut meta = boot();
+    let err = run_source(&mut meta, "(macro (id x) (.RETURN 1))\n(id a))\n(id b)").unwrap_err();
+    assert_eq!(err.span.map(|span| span.start.line), Some(2));
+    assert!(meta.get_def(syms::parse::Symbol::intern("id")).is_some());
+    assert_eq!(frame(&meta), "(a)");
+  }
 }
@@ -0,0 +1,53 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use super::span::Span;
+use super::sym::{SymItem, SymParseError};
+
+// Everything that can go wrong turning source text into machine elements. Unlike
+// the stage specific errors it owns the offending item, so it can outlive the
+// parsed tree.
+#[derive(Debug, Clone)]
+pub enum ParseError {
+  Syntax(SymParseError),
+  NotAnInstruction(SymItem),
+  InvalidInstr(SymItem),
+  InvalidArgs(SymItem),
+}
+
+impl ParseError {
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      ParseError::Syntax(err) => Some(err.span()),
+      ParseError::NotAnInstruction(sym) => sym.span(),
+      ParseError::InvalidInstr(sym) => sym.span(),
+      ParseError::InvalidArgs(sym) => sym.span(),
+    }
+  }
+}
+
+impl From<SymParseError> for ParseError {
+  fn from(err: SymParseError) -> Self {
+    ParseError::Syntax(err)
+  }
+}
+
+impl Display for ParseError {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      ParseError::Syntax(err) => write!(fmt, "{}", err),
+      ParseError::NotAnInstruction(sym) => write!(fmt, "`{}` is not a machine instruction", sym),
+      ParseError::InvalidInstr(sym) => write!(fmt, "invalid machine instruction `{}`", sym),
+      ParseError::InvalidArgs(sym) => write!(fmt, "invalid instruction arguments in `{}`", sym),
+    }
+  }
+}
+
+impl Error for ParseError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ParseError::Syntax(err) => Some(err),
+      _ => None,
+    }
+  }
+}
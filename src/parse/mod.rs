@@ -1,6 +1,18 @@
+mod error;
+mod number;
+mod program;
+mod span;
+mod stream;
 mod sym;
+mod symbol;
 
-pub use sym::{SymItem, SymAtom, SymList};
+pub use error::ParseError;
+pub use number::Number;
+pub use program::{parse_recovering, ProgramReader};
+pub use span::{Location, SourceChars, Span};
+pub use stream::{StreamError, StreamReader};
+pub use sym::{Delimiter, SymAtom, SymItem, SymList, SymListItem, SymParseError, SymStr};
+pub use symbol::{Symbol, QUASIQUOTE, QUOTE, UNQUOTE, UNQUOTE_SPLICING};
 
 
 // enum ExprDisplayModeType {
@@ -25,23 +37,368 @@ pub use sym::{SymItem, SymAtom, SymList};
 //   }
 // }
 
-#[allow(dead_code)]
-fn test_patterns() {
-  if let Some(res) = SymItem::parse("abc") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse(")abc") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse("(abc") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse(" abc") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse("abc(") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse("abc)") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse("abc ") { println!("{:?}", res); }
-  if let Some(res) = SymItem::parse("(ab)") { println!("{}", res); }
-  if let Some(res) = SymItem::parse("(ab cd) ") { println!("{}", res); }
-  if let Some(res) = SymItem::parse("()") { println!("{}", res); }
-  if let Some(res) = SymItem::parse("(((a) bc (d e f) () (() g h) (i())) a)") { println!("{}", res); }
-
-  unimplemented!("Test incomplete for unwrap_or arguments.");
-  // if let Some(res) = SymItem::parse("hello") { println!("{}", res.as_str().unwrap_or("INVALID")) }
-  // if let Some(res) = SymItem::parse("hello") { println!("{:?}", res.as_list().unwrap_or(&vec![])) }
-  // if let Some(res) = SymItem::parse("(a b c)") { println!("{}", res.as_str().unwrap_or("INVALID")) }
-  // if let Some(res) = SymItem::parse("(a b c)") { println!("{:?}", res.as_list().unwrap_or(&vec![])) }
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // What `SymItem::parse` makes of each input: the item as displayed, or the error
+  // message. The first block is the original `test_patterns` set.
+  const CORPUS: &[(&str, Result<&str, &str>)] = &[
+    ("abc", Ok("abc")),
+    (")abc", Err("Unexpected ) at start of SymItem.")),
+    ("(abc", Err("EOF when building SymList.")),
+    (" abc", Ok("abc")),
+    ("abc(", Err("Extra input after SymItem.")),
+    ("abc)", Err("Extra input after SymItem.")),
+    ("abc ", Ok("abc")),
+    ("(ab)", Ok("(ab)")),
+    ("(ab cd) ", Ok("(ab cd)")),
+    ("()", Ok("()")),
+    ("(((a) bc (d e f) () (() g h) (i())) a)", Ok("(((a) bc (d e f) () (() g h) (i ())) a)")),
+
+    ("", Err("Empty input when building SymItem.")),
+    ("( ab )", Ok("(ab)")),
+    ("(\n)", Ok("()")),
+    ("(ab\tcd)", Ok("(ab cd)")),
+    ("\tab\t", Ok("ab")),
+    ("ab\tcd", Err("Extra input after SymItem.")),
+    ("(ab\rcd)", Ok("(ab cd)")),
+    ("(ab\r\ncd)\r\n", Ok("(ab cd)")),
+    ("\r\n(\r\nab\r\n)", Ok("(ab)")),
+    ("(ab\u{0b}cd\u{0c}ef)", Ok("(ab cd ef)")),
+    ("(ab\u{a0}cd\u{2003}ef\u{3000})", Ok("(ab cd ef)")),
+    ("(ab\u{2028}cd)", Ok("(ab cd)")),
+
+    ("(ab ; note\r\ncd)", Ok("(ab cd)")),
+    ("(ab;note\ncd)", Ok("(ab cd)")),
+    ("(ab #| x #| y |# z |# cd)", Ok("(ab cd)")),
+    ("(ab #;(x y) cd)", Ok("(ab cd)")),
+    ("(ab #; #; x y cd)", Ok("(ab cd)")),
+    ("ab ; note", Ok("ab")),
+    ("(ab #| x", Err("EOF inside block comment.")),
+    ("(ab #;", Err("EOF after datum comment.")),
+
+    ("\"ab cd\"", Ok("\"ab cd\"")),
+    ("(ab \"(cd)\" ef)", Ok("(ab \"(cd)\" ef)")),
+    ("(ab\"cd\")", Ok("(ab \"cd\")")),
+    ("\"a\\n\\t\\r\\0\\\"\\\\\\u{41}\\u{1F600}\"", Ok("\"a\\n\\t\\r\\0\\\"\\\\A\u{1F600}\"")),
+    ("\"a\u{7}\"", Ok("\"a\\u{7}\"")),
+    ("\"ab ; cd\"", Ok("\"ab ; cd\"")),
+    ("ab\"cd\"", Err("Extra input after SymItem.")),
+    ("\"ab", Err("EOF inside string literal.")),
+    ("\"ab\\", Err("EOF inside string literal.")),
+    ("\"\\q\"", Err("Invalid escape in string literal.")),
+    ("\"\\u{110000}\"", Err("Invalid escape in string literal.")),
+    ("\"\\u{+41}\"", Err("Invalid escape in string literal.")),
+    ("\"\\u41\"", Err("Invalid escape in string literal.")),
+
+    ("'a", Ok("(quote a)")),
+    ("'(a b)", Ok("(quote (a b))")),
+    ("`(a ,b ,@c)", Ok("(quasiquote (a (unquote b) (unquote-splicing c)))")),
+    ("(a ' b)", Ok("(a (quote b))")),
+    ("''a", Ok("(quote (quote a))")),
+    ("(a '#;b c)", Ok("(a (quote c))")),
+    ("(a ')", Err("Unexpected ) at start of SymItem.")),
+    ("'", Err("EOF after quote.")),
+
+    ("(a . b)", Ok("(a . b)")),
+    ("(a b . c)", Ok("(a b . c)")),
+    ("(a .\tb)", Ok("(a . b)")),
+    ("(a . (b . (c)))", Ok("(a b c)")),
+    ("(a . (b . c))", Ok("(a b . c)")),
+    ("(a . ())", Ok("(a)")),
+    ("(a . 'b)", Ok("(a quote b)")),
+    ("(a .b .c)", Ok("(a .b .c)")),
+    ("(a . \"b\")", Ok("(a . \"b\")")),
+    ("(a . b ; c\n)", Ok("(a . b)")),
+    ("(. a)", Err("Misplaced . in SymList.")),
+    ("(a .)", Err("Misplaced . in SymList.")),
+    ("(a . b c)", Err("Misplaced . in SymList.")),
+    ("(a . b . c)", Err("Misplaced . in SymList.")),
+    ("(a . b", Err("EOF when building SymList.")),
+
+    ("[a b]", Ok("[a b]")),
+    ("{a b}", Ok("{a b}")),
+    ("[]", Ok("[]")),
+    ("{[a] (b) {c}}", Ok("{[a] (b) {c}}")),
+    ("[a . b]", Ok("[a . b]")),
+    ("(a . [b c])", Ok("(a b c)")),
+    ("a[b]", Err("Extra input after SymItem.")),
+    ("(a]", Err("Mismatched ] closing SymList opened with (.")),
+    ("[a)", Err("Mismatched ) closing SymList opened with [.")),
+    ("{a (b})", Err("Mismatched } closing SymList opened with (.")),
+    ("[a . b}", Err("Mismatched } closing SymList opened with [.")),
+    ("]", Err("Unexpected ] at start of SymItem.")),
+    ("}a", Err("Unexpected } at start of SymItem.")),
+    ("[a", Err("EOF when building SymList.")),
+  ];
+
+  #[test]
+  fn corpus() {
+    for (input, expected) in CORPUS {
+      let result = SymItem::parse(input).map(|item| item.to_string()).map_err(|err| err.to_string());
+      assert_eq!(result.as_deref().map_err(|err| err.as_str()), *expected, "input {:?}", input);
+    }
+  }
+
+  #[test]
+  fn numbers() {
+    let cases: &[(&str, Option<Number>)] = &[
+      ("0", Some(Number::Integer(0))),
+      ("42", Some(Number::Integer(42))),
+      ("-7", Some(Number::Integer(-7))),
+      ("+7", Some(Number::Integer(7))),
+      ("0x1F", Some(Number::Integer(31))),
+      ("-0x10", Some(Number::Integer(-16))),
+      ("0b101", Some(Number::Integer(5))),
+      ("-9223372036854775808", Some(Number::Integer(i64::MIN))),
+      ("1/2", Some(Number::Rational(1, 2))),
+      ("-6/4", Some(Number::Rational(-3, 2))),
+      ("0/5", Some(Number::Integer(0))),
+      ("8/4", Some(Number::Integer(2))),
+      ("abc", None),
+      ("-", None),
+      ("+", None),
+      ("-abc", None),
+      (".INDEX", None),
+      ("a1", None),
+      // Atoms that merely start like a number are symbols.
+      ("1st", None),
+      ("1+", None),
+      ("2nd-arg", None),
+      ("-1-", None),
+      ("0x", None),
+      ("0b2", None),
+      ("0xG", None),
+      ("1/", None),
+      ("0x1/2", None),
+      ("1/-2", None),
+      ("1/2/3", None),
+      ("1.5", None),
+    ];
+    for (input, expected) in cases {
+      let item = SymItem::parse(input).unwrap();
+      assert_eq!(item.as_number(), *expected, "input {:?}", input);
+      assert_eq!(item.as_str().is_some(), expected.is_none(), "input {:?}", input);
+      assert_eq!(item.to_string(), *input);
+    }
+
+    for input in ["1/0", "-0/0", "9223372036854775808", "0x8000000000000000", "1/99999999999999999999"] {
+      let err = SymItem::parse(input).unwrap_err();
+      assert_eq!(err.to_string(), "Malformed or out of range number.", "input {:?}", input);
+    }
+  }
+
+  #[test]
+  fn interning() {
+    assert_eq!(Symbol::intern("quote"), QUOTE);
+    assert_eq!(Symbol::intern(".SPLIT"), Symbol::SPLIT);
+    assert_eq!(Symbol::intern("interned").as_str(), "interned");
+
+    let item = SymItem::parse("(foo 'foo 12)").unwrap();
+    let items = item.as_list().unwrap().iter().map(|item| item.into_inner_early().unwrap()).collect::<Vec<_>>();
+    assert_eq!(items[0].as_symbol(), Some(Symbol::intern("foo")));
+    assert_eq!(items[1].index_early(0).unwrap().as_symbol(), Some(QUOTE));
+    assert_eq!(items[1].index_early(1).unwrap().as_symbol(), items[0].as_symbol());
+    assert_eq!(items[2].as_symbol(), None);
+  }
+
+  #[test]
+  fn structural_equality() {
+    use crate::primitives::MetaElement;
+    use std::collections::HashSet;
+    use std::hash::{BuildHasher, RandomState};
+
+    let hasher = RandomState::new();
+    let a = SymItem::parse("(a [b 0x10] \"s\" . c)").unwrap();
+    let b = SymItem::parse(" (a (b 16)\n \"s\" . c)").unwrap();
+    assert_eq!(a, b);
+    assert_eq!(hasher.hash_one(&a), hasher.hash_one(&b));
+    assert_ne!(a, SymItem::parse("(a (b 16) \"s\" c)").unwrap());
+    assert_ne!(SymItem::parse("a").unwrap(), SymItem::parse("\"a\"").unwrap());
+
+    // Converted lists still equal the syntax they came from, but an instruction
+    // differs from its list.
+    let elem = MetaElement::try_from(&a).unwrap();
+    assert_eq!(elem, MetaElement::Expr(b.clone()));
+    assert_eq!(hasher.hash_one(&elem), hasher.hash_one(MetaElement::Expr(b)));
+    let inst = SymItem::parse("(.RETURN 1)").unwrap();
+    assert_ne!(MetaElement::try_from(&inst).unwrap(), MetaElement::Expr(inst.clone()));
+    assert_eq!(MetaElement::parse("(x (.RETURN 1))").unwrap(), MetaElement::parse("(x [.RETURN 1])").unwrap());
+
+    let mut items = ["b", "(a)", "2", "\"s\"", "a", "1/2", "()", "0x2"].map(|item| SymItem::parse(item).unwrap());
+    items.sort();
+    assert_eq!(items.iter().map(|item| item.to_string()).collect::<Vec<_>>(),
+	       ["1/2", "2", "0x2", "a", "b", "\"s\"", "()", "(a)"]);
+    assert_eq!(items.iter().collect::<HashSet<_>>().len(), 7);
+  }
+
+  // Each stage's failure comes back as a value carrying the offending span.
+  #[test]
+  fn parse_errors() {
+    use crate::primitives::MetaElement;
+    use std::error::Error;
+
+    fn at(err: &ParseError) -> (usize, usize) {
+      let span = err.span().unwrap();
+      (span.start.line, span.start.column)
+    }
+
+    let err = MetaElement::parse("(a\n  (b").unwrap_err();
+    assert!(matches!(err, ParseError::Syntax(_)));
+    assert!(err.source().is_some());
+    assert_eq!(err.to_string(), "EOF when building SymList.");
+
+    let err = MetaElement::parse("(x (.DEFINE 1 2))").unwrap_err();
+    assert!(matches!(err, ParseError::InvalidInstr(_)));
+    assert!(err.source().is_none());
+    assert_eq!(at(&err), (1, 4));
+    assert_eq!(err.to_string(), "invalid machine instruction `(.DEFINE 1 2)`");
+
+    let err = MetaElement::parse("(x\n (.INDEX 0 a))").unwrap_err();
+    assert!(matches!(err, ParseError::InvalidArgs(_)));
+    assert_eq!(at(&err), (2, 12));
+
+    let err = SymItem::parse("(.RETURN 1 . 2)").and_then(|item| Ok(MetaElement::try_from(&item)?)).unwrap_err();
+    assert!(matches!(err, ParseError::InvalidInstr(_)));
+    assert_eq!(at(&err), (1, 1));
+  }
+
+  #[test]
+  fn crlf_locations() {
+    let item = SymItem::parse("(ab\r\n  cd\r\n)").unwrap();
+    let list = item.as_list().unwrap();
+    let cd = list[1].into_inner_early().unwrap().span().unwrap();
+    assert_eq!((cd.start.line, cd.start.column), (2, 3));
+    assert_eq!((cd.end.line, cd.end.column), (2, 5));
+    let end = list.span().unwrap().end;
+    assert_eq!((end.line, end.column), (3, 2));
+  }
+
+  #[test]
+  fn late_stage_copy() {
+    fn assert_shareable<T: Send + Sync>() {}
+    assert_shareable::<crate::primitives::MetaElement>();
+
+    let item = SymItem::parse("(a (.RETURN 1) (b . c))").unwrap();
+    let before = format!("{:?}", item);
+    let elem = crate::primitives::MetaElement::try_from(&item).unwrap();
+    assert_eq!(format!("{:?}", item), before);
+    assert!(item.as_list().unwrap().iter().all(|item| item.into_inner_early().is_some()));
+
+    let list = elem.as_list().unwrap();
+    assert_eq!(list.into_iter().map(|elem| elem.to_string()).collect::<Vec<_>>(), ["a", "(.RETURN 1)", "(b . c)"]);
+    assert!(matches!(list.into_iter().nth(1), Some(crate::primitives::MetaElement::Instr(_, _))));
+    assert!(list.dotted_tail().is_none());
+    assert_eq!(list.into_iter().last().unwrap().as_list().unwrap().dotted_tail().unwrap().as_str(), Some("c"));
+  }
+
+  #[test]
+  fn recovering() {
+    let cases: &[(&str, &[&str], &[&str])] = &[
+      ("(a 1/0) (b \"\\q\") ]", &["(a #<error>)", "(b #<error>)", "#<error>"],
+       &["Malformed or out of range number.", "Invalid escape in string literal.", "Unexpected ] at start of SymItem."]),
+      ("(a]\n(b . c d)\n'", &["(a)", "(b #<error> c d)", "(quote #<error>)"],
+       &["Mismatched ] closing SymList opened with (.", "Misplaced . in SymList.", "EOF after quote."]),
+      ("(macro (f x)\n  (g x\n(h) #| x", &["(macro (f x) (g x))", "(h)"],
+       &["EOF when building SymList.", "EOF when building SymList.", "EOF inside block comment."]),
+      ("(macro (f)\n(g)) \"ok\"", &["(macro (f) (g))", "\"ok\""], &[]),
+    ];
+    for (source, items, errors) in cases {
+      let (read, found) = parse_recovering(source);
+      assert_eq!(read.iter().map(|item| item.to_string()).collect::<Vec<_>>(), *items, "items of {:?}", source);
+      assert_eq!(found.iter().map(|err| err.to_string()).collect::<Vec<_>>(), *errors, "errors of {:?}", source);
+    }
+
+    let (_, found) = parse_recovering("(a\n  (b 1/0)\n(c)");
+    let spans = found.iter().map(|err| (err.span().start.line, err.span().end.line)).collect::<Vec<_>>();
+    assert_eq!(spans, [(1, 3), (2, 2)]);
+  }
+
+  // Hands out its input a few bytes per read, the way a socket might.
+  struct Trickle<'a>(&'a [u8], usize);
+
+  impl std::io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let len = self.1.min(self.0.len()).min(buf.len());
+      buf[..len].copy_from_slice(&self.0[..len]);
+      self.0 = &self.0[len..];
+      Ok(len)
+    }
+  }
+
+  fn stream(source: &str, chunk: usize) -> Vec<Result<String, String>> {
+    StreamReader::from_read(Trickle(source.as_bytes(), chunk))
+      .map(|item| match item {
+	Ok(item) => Ok(item.to_string()),
+	Err(StreamError::Incomplete(_)) => Err("incomplete".to_string()),
+	Err(err) => Err(err.to_string()),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn stream_chunks() {
+    let source = "(a b) c \"x\\u{41} y\" ; note\r\n[d . e] 0x1F #| x |# 'f ,@g é";
+    let expected = ["(a b)", "c", "\"xA y\"", "[d . e]", "0x1F", "(quote f)", "(unquote-splicing g)", "é"]
+      .map(|item| Ok(item.to_string()));
+    for chunk in [1, 2, 3, 7, source.len()] {
+      assert_eq!(stream(source, chunk), expected, "chunk {}", chunk);
+    }
+  }
+
+  #[test]
+  fn stream_errors() {
+    for chunk in [1, 4, 64] {
+      assert_eq!(stream("(a b", chunk), [Err("incomplete".to_string())]);
+      assert_eq!(stream("a \"b", chunk), [Ok("a".to_string()), Err("incomplete".to_string())]);
+      assert_eq!(stream("#| a", chunk), [Err("incomplete".to_string())]);
+      assert_eq!(stream("(a]\n(b)", chunk),
+		 [Err("Mismatched ] closing SymList opened with (.".to_string()), Ok("(b)".to_string())]);
+      assert_eq!(stream("(a 1/0 b) (c \"\\q\" d) ((e . f g) h) [i} (j)", chunk),
+		 [Err("Malformed or out of range number.".to_string()), Err("Invalid escape in string literal.".to_string()),
+		  Err("Misplaced . in SymList.".to_string()), Err("Mismatched } closing SymList opened with [.".to_string()),
+		  Ok("(j)".to_string())]);
+      assert_eq!(stream("#;(a 1/0) 'b\n(c\n (d \")\")) f", chunk),
+		 [Err("Malformed or out of range number.".to_string()), Ok("(quote b)".to_string()), Ok("(c (d \")\"))".to_string()), Ok("f".to_string())]);
+      assert_eq!(stream("1/0", chunk), [Err("Malformed or out of range number.".to_string())]);
+    }
+  }
+
+  // Comments, strings and datum comments split at any point still read the same.
+  #[test]
+  fn stream_scanning() {
+    let source = "#| a #| (b |# c |# (d #;(e) \"f\\\" )\" ; g)\n h) #;i j 'k ,@(l) \"m\" ]n #|#|x|#|#o";
+    let expected = [Ok("(d \"f\\\" )\" h)"), Ok("j"), Ok("(quote k)"), Ok("(unquote-splicing (l))"), Ok("\"m\""),
+		    Err("Unexpected ] at start of SymItem."), Ok("n"), Ok("o")]
+      .map(|item| item.map(str::to_string).map_err(str::to_string));
+    for chunk in 1..8 {
+      assert_eq!(stream(source, chunk), expected, "chunk {}", chunk);
+    }
+  }
+
+  // An item arriving a byte at a time is not parsed again with every byte.
+  #[test]
+  fn stream_long_item() {
+    let source = format!("({})", "(a \"b\" ; c\n) ".repeat(20000));
+    let items = stream(&source, 1);
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].as_ref().map(|item| item.len()), Ok(20000 * 8 + 1));
+  }
+
+  #[test]
+  fn stream_locations() {
+    let mut reader = StreamReader::from_read(Trickle(b"(a)\n  (b\n c)", 1));
+    reader.next().unwrap().unwrap();
+    let span = reader.next().unwrap().unwrap().span().unwrap();
+    assert_eq!((span.start.offset, span.start.line, span.start.column), (6, 2, 3));
+    assert_eq!((span.end.offset, span.end.line, span.end.column), (12, 3, 4));
+  }
+
+  #[test]
+  fn program_whitespace() {
+    let source = "a\r\n(b\tc)\u{a0}d ; e\r\n";
+    let items = ProgramReader::new(source).map(|item| item.unwrap().to_string()).collect::<Vec<_>>();
+    assert_eq!(items, ["a", "(b c)", "d"]);
+  }
 }
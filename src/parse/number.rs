@@ -0,0 +1,112 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+
+// Value of a numeric atom. Rationals are kept in lowest terms with a denominator
+// above 1, so every number has exactly one representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Number {
+  Integer(i64),
+  Rational(i64, i64),
+}
+
+impl Number {
+  // Builds `numer/denom` in lowest terms, or `None` when `denom` is 0 or the
+  // result does not fit.
+  pub fn rational(numer: i64, denom: i64) -> Option<Self> {
+    if denom == 0 {
+      return None;
+    }
+    let divisor = i64::try_from(gcd(numer.unsigned_abs(), denom.unsigned_abs())).ok()?;
+    let (numer, denom) = if denom < 0 {
+      (numer.checked_neg()? / divisor, denom.checked_neg()? / divisor)
+    }
+    else {
+      (numer / divisor, denom / divisor)
+    };
+
+    if denom == 1 {
+      Some(Number::Integer(numer))
+    }
+    else {
+      Some(Number::Rational(numer, denom))
+    }
+  }
+
+  pub fn as_integer(&self) -> Option<i64> {
+    match self {
+      Number::Integer(n) => Some(*n),
+      Number::Rational(_, _) => None,
+    }
+  }
+
+  // Classifies the text of an atom. An atom spelled as a number, optionally after
+  // a sign, is one: a decimal, `0x` hex or `0b` binary integer, or a decimal `n/d`
+  // rational. Anything else, `1st` or `0x` included, is a plain symbol and gives
+  // `Ok(None)`. `Err(())` means the atom is spelled as a number but is out of range
+  // or has a zero denominator.
+  pub(super) fn read(text: &str) -> Result<Option<Self>, ()> {
+    let (sign, digits) = match text.strip_prefix('-') {
+      Some(rest) => ("-", rest),
+      None => ("", text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let (radix, digits) = if let Some(hex) = digits.strip_prefix("0x") {
+      (16, hex)
+    }
+    else if let Some(bin) = digits.strip_prefix("0b") {
+      (2, bin)
+    }
+    else {
+      (10, digits)
+    };
+    let (numer, denom) = match digits.split_once('/') {
+      Some((numer, denom)) if radix == 10 => (numer, Some(denom)),
+      _ => (digits, None),
+    };
+    let spelled = |digits: &str, radix| !digits.is_empty() && digits.chars().all(|c: char| c.is_digit(radix));
+    if !spelled(numer, radix) || !denom.is_none_or(|denom| spelled(denom, 10)) {
+      return Ok(None);
+    }
+
+    let numer = parse_integer(sign, numer, radix)?;
+    match denom {
+      Some(denom) => Number::rational(numer, parse_integer("", denom, 10)?).map(Some).ok_or(()),
+      None => Ok(Some(Number::Integer(numer))),
+    }
+  }
+}
+
+// Numbers order by value.
+impl Ord for Number {
+  fn cmp(&self, other: &Self) -> Ordering {
+    let parts = |number: &Number| match *number {
+      Number::Integer(n) => (n as i128, 1),
+      Number::Rational(numer, denom) => (numer as i128, denom as i128),
+    };
+    let ((a, b), (c, d)) = (parts(self), parts(other));
+    (a * d).cmp(&(c * b))
+  }
+}
+
+impl PartialOrd for Number {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Display for Number {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      Number::Integer(n) => write!(fmt, "{}", n),
+      Number::Rational(numer, denom) => write!(fmt, "{}/{}", numer, denom),
+    }
+  }
+}
+
+fn parse_integer(sign: &str, digits: &str, radix: u32) -> Result<i64, ()> {
+  i64::from_str_radix(&format!("{}{}", sign, digits), radix).map_err(|_| ())
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+  if b == 0 { a.max(1) } else { gcd(b, a % b) }
+}
@@ -0,0 +1,73 @@
+use super::span::SourceChars;
+use super::sym::{skip_blank, skip_blank_with, Reader, SymItem, SymParseError};
+
+// Reads a whole program: a sequence of top-level items separated by whitespace
+// and comments.
+// Reading stops at the first item that fails to parse.
+pub struct ProgramReader<'a> {
+  chars: SourceChars<'a>,
+}
+
+impl<'a> ProgramReader<'a> {
+  pub fn new(source: &'a str) -> Self {
+    ProgramReader {
+      chars: SourceChars::new(source),
+    }
+  }
+}
+
+impl<'a> Iterator for ProgramReader<'a> {
+  type Item = Result<SymItem, SymParseError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let result = skip_blank(&mut self.chars).and_then(|()| {
+      if self.chars.as_str().is_empty() {
+	return Ok(None);
+      }
+      SymItem::try_from(&mut self.chars).map(Some)
+    });
+    if result.is_err() {
+      self.chars.seek(self.chars.source().len());
+    }
+    result.transpose()
+  }
+}
+
+// Reads every item of `source`, stepping over syntax errors instead of stopping at
+// the first. Returns the best-effort items, with a `SymItem::SymError` wherever
+// text could not be read, and every error found, in source order.
+//
+// A list left open would swallow the rest of the input, so an item that runs into
+// the end of the input is read again up to the next `(` at the start of a line,
+// where the next top-level form most likely begins.
+pub fn parse_recovering(source: &str) -> (Vec<SymItem>, Vec<SymParseError>) {
+  let mut chars = SourceChars::new(source);
+  let mut reader = Reader::recovering();
+  let mut items = vec![];
+  let mut errors = vec![];
+
+  loop {
+    skip_blank_with(&mut chars, &mut reader).expect("recovering reader failed");
+    if chars.as_str().is_empty() {
+      break;
+    }
+    let start = chars.offset();
+    let mut item_reader = Reader::recovering();
+    let mut item = SymItem::read(&mut chars, &mut item_reader).expect("recovering reader failed");
+
+    let resync = source[start..].match_indices("\n(").next().map(|(idx, _)| start + idx + 1);
+    if let Some(resync) = resync.filter(|_| item_reader.errors().iter().any(|err| err.is_incomplete())) {
+      let mut window = SourceChars::with_origin(&source[start..resync], chars.location(start));
+      item_reader = Reader::recovering();
+      item = SymItem::read(&mut window, &mut item_reader).expect("recovering reader failed");
+      chars.seek(start + window.offset());
+    }
+
+    items.push(item);
+    errors.extend(item_reader.into_errors());
+  }
+
+  errors.extend(reader.into_errors());
+  errors.sort_by_key(|err| err.span().start.offset);
+  (items, errors)
+}
@@ -0,0 +1,111 @@
+use std::fmt::{self, Display};
+use std::ops::{Deref, DerefMut};
+use std::str::Chars;
+
+// Position in the source text. `line` and `column` start at 1 and columns count
+// characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Location {
+  pub offset: usize,
+  pub line: usize,
+  pub column: usize,
+}
+
+impl Location {
+  pub const START: Location = Location { offset: 0, line: 1, column: 1 };
+}
+
+// Half-open byte range `start..end` of the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+  pub start: Location,
+  pub end: Location,
+}
+
+impl Display for Span {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    write!(fmt, "{}:{}", self.start.line, self.start.column)
+  }
+}
+
+// Characters of a source text that know where they are in it. Derefs to the
+// remaining `Chars` so the reader can consume input as before.
+pub struct SourceChars<'a> {
+  source: &'a str,
+  origin: Location,
+  line_starts: Vec<usize>,
+  chars: Chars<'a>,
+}
+
+impl<'a> SourceChars<'a> {
+  pub fn new(source: &'a str) -> Self {
+    Self::with_origin(source, Location::START)
+  }
+
+  // Source that starts at `origin` of some larger text, such as the unread part of
+  // a stream. Locations are reported relative to that text; offsets taken and given
+  // by `offset` and `seek` stay relative to `source`.
+  pub fn with_origin(source: &'a str, origin: Location) -> Self {
+    let line_starts = std::iter::once(0)
+      .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+      .collect();
+
+    SourceChars {
+      source,
+      origin,
+      line_starts,
+      chars: source.chars(),
+    }
+  }
+
+  pub fn source(&self) -> &'a str {
+    self.source
+  }
+
+  pub fn offset(&self) -> usize {
+    self.source.len() - self.chars.as_str().len()
+  }
+
+  pub fn location(&self, offset: usize) -> Location {
+    let line = self.line_starts.partition_point(|&start| start <= offset);
+    let line_start = self.line_starts[line - 1];
+    let column = self.source[line_start..offset].chars().count() + 1;
+    Location {
+      offset: self.origin.offset + offset,
+      line: self.origin.line + line - 1,
+      column: if line == 1 { self.origin.column + column - 1 } else { column },
+    }
+  }
+
+  // Span from `start` up to the current position.
+  pub fn span_from(&self, start: usize) -> Span {
+    Span {
+      start: self.location(start),
+      end: self.location(self.offset()),
+    }
+  }
+
+  // Empty span at the current position.
+  pub fn here(&self) -> Span {
+    self.span_from(self.offset())
+  }
+
+  // Moves to `offset`, usually to skip ahead.
+  pub fn seek(&mut self, offset: usize) {
+    self.chars = self.source[offset..].chars();
+  }
+}
+
+impl<'a> Deref for SourceChars<'a> {
+  type Target = Chars<'a>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.chars
+  }
+}
+
+impl<'a> DerefMut for SourceChars<'a> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.chars
+  }
+}
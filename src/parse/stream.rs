@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::span::{Location, SourceChars, Span};
+use super::sym::{ends_atom, skip_blank, Delimiter, SymItem, SymParseError};
+
+#[derive(Debug)]
+pub enum StreamError {
+  Io(io::Error),
+  Syntax(SymParseError),
+  // The stream ended inside an item.
+  Incomplete(SymParseError),
+}
+
+impl StreamError {
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      StreamError::Io(_) => None,
+      StreamError::Syntax(err) | StreamError::Incomplete(err) => Some(err.span()),
+    }
+  }
+}
+
+impl From<io::Error> for StreamError {
+  fn from(err: io::Error) -> Self {
+    StreamError::Io(err)
+  }
+}
+
+impl Display for StreamError {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      StreamError::Io(err) => write!(fmt, "{}", err),
+      StreamError::Syntax(err) => write!(fmt, "{}", err),
+      StreamError::Incomplete(err) => write!(fmt, "Incomplete input: {}", err),
+    }
+  }
+}
+
+impl Error for StreamError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      StreamError::Io(err) => Some(err),
+      StreamError::Syntax(err) | StreamError::Incomplete(err) => Some(err),
+    }
+  }
+}
+
+// Reads top-level items from a byte stream as they arrive. Only the text of the
+// item being read is buffered, and each item is yielded as soon as it closes, which
+// for an atom means once the character after it has been read. Locations count
+// from the start of the stream.
+//
+// New input is scanned once for brackets, strings and comments, and the buffer is
+// only parsed when the scan finds a place a top-level item may end, so reading an
+// item a few bytes at a time stays linear in its length.
+//
+// A syntax error discards the rest of the top-level item holding it, or the
+// offending text itself when that runs further, and reading carries on after it. An item still open when the stream ends is
+// reported as `StreamError::Incomplete`.
+pub struct StreamReader<R> {
+  input: R,
+  buffer: String,
+  // Leading bytes of a character split across reads.
+  partial: Vec<u8>,
+  origin: Location,
+  eof: bool,
+  scan: Scan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lexeme {
+  Blank,
+  Atom,
+  Str,
+  Escape,
+  LineComment,
+  BlockComment(usize),
+}
+
+// How far the buffer has been scanned and what the scan was inside of there.
+#[derive(Debug, Clone, Copy)]
+struct Scan {
+  pos: usize,
+  depth: usize,
+  lexeme: Lexeme,
+  // Whether a top-level item has begun, and the length of the blank text before it.
+  started: bool,
+  blank: usize,
+}
+
+impl Scan {
+  const START: Scan = Scan { pos: 0, depth: 0, lexeme: Lexeme::Blank, started: false, blank: 0 };
+
+  // Scans on through `buffer`, stopping just past the first place a top-level item
+  // may end. Returns whether it found one; otherwise the scan waits at the end of
+  // the buffer, or before a `#` or `|` whose meaning depends on what follows.
+  fn advance(&mut self, buffer: &str, eof: bool) -> bool {
+    while let Some(c) = buffer[self.pos..].chars().next() {
+      let next = buffer[self.pos + c.len_utf8()..].chars().next();
+      if next.is_none() && !eof && (c == '#' || c == '|' && matches!(self.lexeme, Lexeme::BlockComment(_))) {
+	return false;
+      }
+
+      let mut len = c.len_utf8();
+      let mut ended = false;
+      match self.lexeme {
+	Lexeme::Blank if c.is_whitespace() => (),
+	Lexeme::Blank if c == ';' => self.lexeme = Lexeme::LineComment,
+	Lexeme::Blank if c == '#' && next == Some('|') => {
+	  self.lexeme = Lexeme::BlockComment(1);
+	  len = 2;
+	},
+	Lexeme::Blank => {
+	  self.started |= self.depth == 0;
+	  if c == '#' && next == Some(';') {
+	    len = 2;
+	  }
+	  else if c == '"' {
+	    self.lexeme = Lexeme::Str;
+	  }
+	  else if Delimiter::from_open(c).is_some() {
+	    self.depth += 1;
+	  }
+	  else if Delimiter::from_close(c).is_some() {
+	    // A stray bracket at the top level is for the parser to report.
+	    ended = self.depth <= 1;
+	    self.depth = self.depth.saturating_sub(1);
+	  }
+	  else if !matches!(c, '\'' | '`' | ',') {
+	    self.lexeme = Lexeme::Atom;
+	  }
+	},
+	Lexeme::Atom if ends_atom(c) => {
+	  self.lexeme = Lexeme::Blank;
+	  if self.depth == 0 {
+	    return true;
+	  }
+	  continue;
+	},
+	Lexeme::Atom => (),
+	Lexeme::Str if c == '"' => {
+	  self.lexeme = Lexeme::Blank;
+	  ended = self.depth == 0;
+	},
+	Lexeme::Str if c == '\\' => self.lexeme = Lexeme::Escape,
+	Lexeme::Str => (),
+	Lexeme::Escape => self.lexeme = Lexeme::Str,
+	Lexeme::LineComment if c == '\n' => self.lexeme = Lexeme::Blank,
+	Lexeme::LineComment => (),
+	Lexeme::BlockComment(nesting) if c == '#' && next == Some('|') => {
+	  self.lexeme = Lexeme::BlockComment(nesting + 1);
+	  len = 2;
+	},
+	Lexeme::BlockComment(nesting) if c == '|' && next == Some('#') => {
+	  self.lexeme = if nesting == 1 { Lexeme::Blank } else { Lexeme::BlockComment(nesting - 1) };
+	  len = 2;
+	},
+	Lexeme::BlockComment(_) => (),
+      }
+      self.pos += len;
+      if !self.started && matches!(self.lexeme, Lexeme::Blank | Lexeme::LineComment) {
+	self.blank = self.pos;
+      }
+      if ended {
+	return true;
+      }
+    }
+    false
+  }
+}
+
+impl<R: Read> StreamReader<BufReader<R>> {
+  pub fn from_read(input: R) -> Self {
+    Self::new(BufReader::new(input))
+  }
+}
+
+impl<R: BufRead> StreamReader<R> {
+  pub fn new(input: R) -> Self {
+    StreamReader {
+      input,
+      buffer: String::new(),
+      partial: vec![],
+      origin: Location::START,
+      eof: false,
+      scan: Scan::START,
+    }
+  }
+
+  // Where the unread part of the stream starts.
+  pub fn location(&self) -> Location {
+    self.origin
+  }
+
+  // Reads whatever the input has ready into the buffer.
+  fn fill(&mut self) -> io::Result<()> {
+    let bytes = self.input.fill_buf()?;
+    if bytes.is_empty() {
+      self.eof = true;
+      if !self.partial.is_empty() {
+	return Err(io::Error::new(io::ErrorKind::InvalidData, "stream ended inside a UTF-8 sequence"));
+      }
+      return Ok(());
+    }
+    self.partial.extend_from_slice(bytes);
+    let len = bytes.len();
+    self.input.consume(len);
+
+    let valid = match std::str::from_utf8(&self.partial) {
+      Ok(text) => text.len(),
+      Err(err) if err.error_len().is_none() => err.valid_up_to(),
+      Err(err) => {
+	self.partial.clear();
+	return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+      },
+    };
+    self.buffer.push_str(std::str::from_utf8(&self.partial[..valid]).unwrap());
+    self.partial.drain(..valid);
+    Ok(())
+  }
+
+  // Drops the first `len` bytes of the buffer, which end an item or an error, and
+  // scans afresh from there.
+  fn consume(&mut self, len: usize) {
+    self.origin = SourceChars::with_origin(&self.buffer[..len], self.origin).location(len);
+    self.buffer.drain(..len);
+    self.scan = Scan::START;
+  }
+
+  // Drops the blank text scanned so far, keeping the scan where it was.
+  fn consume_blank(&mut self) {
+    let len = std::mem::take(&mut self.scan.blank);
+    self.origin = SourceChars::with_origin(&self.buffer[..len], self.origin).location(len);
+    self.buffer.drain(..len);
+    self.scan.pos -= len;
+  }
+
+  // Malformed atoms and escapes that run into the end of the buffer may still be
+  // completed by the input that follows, as in `1/0` followed by `5`.
+  fn at_end(&self, err: &SymParseError) -> bool {
+    matches!(err, SymParseError::InvalidNumber(_) | SymParseError::InvalidEscape(_))
+      && err.span().end.offset == self.origin.offset + self.buffer.len()
+  }
+
+  // The next complete item in the buffer, if there is one yet.
+  fn read_buffered(&mut self) -> Result<Option<SymItem>, SymParseError> {
+    if !self.scan.advance(&self.buffer, self.eof) && !self.eof {
+      self.consume_blank();
+      return Ok(None);
+    }
+
+    let mut chars = SourceChars::with_origin(&self.buffer, self.origin);
+    skip_blank(&mut chars)?;
+    if chars.as_str().is_empty() {
+      if self.eof {
+	self.consume(self.buffer.len());
+      }
+      return Ok(None);
+    }
+
+    let item = SymItem::try_from(&mut chars)?;
+    let end = chars.offset();
+    let closed = self.buffer[..end].ends_with([')', ']', '}', '"']);
+    if end == self.buffer.len() && !closed && !self.eof {
+      return Ok(None);
+    }
+    self.consume(end);
+    Ok(Some(item))
+  }
+}
+
+impl<R: BufRead> Iterator for StreamReader<R> {
+  type Item = Result<SymItem, StreamError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      match self.read_buffered() {
+	Ok(Some(item)) => return Some(Ok(item)),
+	Ok(None) if self.eof => return None,
+	Ok(None) => (),
+	Err(err) if !self.eof && (err.is_incomplete() || self.at_end(&err)) => (),
+	Err(err) => {
+	  // Skip past the error and carry on from there. The scan stopped at the end
+	  // of the top-level item the parser was reading, so that is skipped too.
+	  let len = if err.is_incomplete() { self.buffer.len() } else { (err.span().end.offset - self.origin.offset).max(self.scan.pos) };
+	  self.consume(len.min(self.buffer.len()));
+	  return Some(Err(if err.is_incomplete() { StreamError::Incomplete(err) } else { StreamError::Syntax(err) }));
+	},
+      }
+
+      if let Err(err) = self.fill() {
+	return Some(Err(err.into()));
+      }
+    }
+  }
+}
@@ -1,65 +1,263 @@
+use std::cmp::Ordering;
 use std::convert::TryFrom;
-use std::str::Chars;
-use std::fmt::{self, Display};
+use std::error::Error;
+use std::fmt::{self, Display, Write};
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
+use super::error::ParseError;
+use super::number::Number;
+use super::span::{SourceChars, Span};
+use super::symbol::{Symbol, QUASIQUOTE, QUOTE, UNQUOTE, UNQUOTE_SPLICING};
 use crate::primitives::MetaElement;
 
 #[derive(Debug, Clone)]
 pub enum SymParseError {
-  SymItemExtraInput(String),
-  SymItemEOF(String),
-  SymListEOF(String),
-  SymAtomNoTerminal(String),
-  SymAtomEOF(String),
-  InvalidStartOfInput,
+  SymItemExtraInput(String, Span),
+  SymItemEOF(String, Span),
+  SymListEOF(String, Span),
+  SymAtomNoTerminal(String, Span),
+  SymAtomEOF(String, Span),
+  InvalidStartOfInput(char, Span),
+  UnterminatedBlockComment(Span),
+  DatumCommentEOF(Span),
+  SymStrEOF(Span),
+  InvalidEscape(Span),
+  InvalidNumber(Span),
+  QuoteEOF(Span),
+  InvalidDottedList(Span),
+  MismatchedBracket(char, Span, char, Span),
+}
+
+impl SymParseError {
+  pub fn span(&self) -> Span {
+    match self {
+      SymParseError::SymItemExtraInput(_, span) => *span,
+      SymParseError::SymItemEOF(_, span) => *span,
+      SymParseError::SymListEOF(_, span) => *span,
+      SymParseError::SymAtomNoTerminal(_, span) => *span,
+      SymParseError::SymAtomEOF(_, span) => *span,
+      SymParseError::InvalidStartOfInput(_, span) => *span,
+      SymParseError::UnterminatedBlockComment(span) => *span,
+      SymParseError::DatumCommentEOF(span) => *span,
+      SymParseError::SymStrEOF(span) => *span,
+      SymParseError::InvalidEscape(span) => *span,
+      SymParseError::InvalidNumber(span) => *span,
+      SymParseError::QuoteEOF(span) => *span,
+      SymParseError::InvalidDottedList(span) => *span,
+      SymParseError::MismatchedBracket(_, _, _, span) => *span,
+    }
+  }
+
+  // True when the input ended inside an item, so more input could still complete
+  // it, as opposed to input that is malformed whatever follows.
+  pub fn is_incomplete(&self) -> bool {
+    matches!(self, SymParseError::SymListEOF(_, _)
+	     | SymParseError::SymAtomEOF(_, _)
+	     | SymParseError::UnterminatedBlockComment(_)
+	     | SymParseError::DatumCommentEOF(_)
+	     | SymParseError::SymStrEOF(_)
+	     | SymParseError::QuoteEOF(_))
+  }
+}
+
+impl Display for SymParseError {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      SymParseError::SymItemExtraInput(msg, _)
+	| SymParseError::SymItemEOF(msg, _)
+	| SymParseError::SymListEOF(msg, _)
+	| SymParseError::SymAtomNoTerminal(msg, _)
+	| SymParseError::SymAtomEOF(msg, _) => fmt.write_str(msg),
+      SymParseError::InvalidStartOfInput(close, _) => write!(fmt, "Unexpected {} at start of SymItem.", close),
+      SymParseError::UnterminatedBlockComment(_) => fmt.write_str("EOF inside block comment."),
+      SymParseError::DatumCommentEOF(_) => fmt.write_str("EOF after datum comment."),
+      SymParseError::SymStrEOF(_) => fmt.write_str("EOF inside string literal."),
+      SymParseError::InvalidEscape(_) => fmt.write_str("Invalid escape in string literal."),
+      SymParseError::InvalidNumber(_) => fmt.write_str("Malformed or out of range number."),
+      SymParseError::QuoteEOF(_) => fmt.write_str("EOF after quote."),
+      SymParseError::InvalidDottedList(_) => fmt.write_str("Misplaced . in SymList."),
+      SymParseError::MismatchedBracket(open, _, close, _) => write!(fmt, "Mismatched {} closing SymList opened with {}.", close, open),
+    }
+  }
+}
+
+impl Error for SymParseError {}
+
+fn quote_prefix(rest: &str) -> Option<(Symbol, usize)> {
+  if rest.starts_with(",@") {
+    return Some((UNQUOTE_SPLICING, 2));
+  }
+  match rest.chars().next()? {
+    '\'' => Some((QUOTE, 1)),
+    '`' => Some((QUASIQUOTE, 1)),
+    ',' => Some((UNQUOTE, 1)),
+    _ => None,
+  }
+}
+
+// How the reader deals with syntax errors. A strict reader fails on the first
+// one. A recovering reader records it, puts a `SymItem::SymError` in place of
+// the text it could not read and carries on, so one pass finds every error.
+pub(super) struct Reader {
+  recovering: bool,
+  errors: Vec<SymParseError>,
+}
+
+impl Reader {
+  pub(super) fn strict() -> Self {
+    Reader { recovering: false, errors: vec![] }
+  }
+
+  pub(super) fn recovering() -> Self {
+    Reader { recovering: true, errors: vec![] }
+  }
+
+  pub(super) fn errors(&self) -> &[SymParseError] {
+    &self.errors
+  }
+
+  pub(super) fn into_errors(self) -> Vec<SymParseError> {
+    self.errors
+  }
+
+  fn recover(&mut self, err: SymParseError) -> Result<(), SymParseError> {
+    if !self.recovering {
+      return Err(err);
+    }
+    self.errors.push(err);
+    Ok(())
+  }
+}
+
+// Skips whitespace and comments up to the start of the next item or the end of
+// the input. Comments are `;` to the end of the line, `#| ... |#` blocks, which
+// nest, and `#;`, which comments out the item following it.
+pub(super) fn skip_blank(chars: &mut SourceChars) -> Result<(), SymParseError> {
+  skip_blank_with(chars, &mut Reader::strict())
+}
+
+pub(super) fn skip_blank_with(chars: &mut SourceChars, reader: &mut Reader) -> Result<(), SymParseError> {
+  loop {
+    let rest = chars.as_str();
+    if rest.starts_with(|c: char| c.is_whitespace()) {
+      chars.seek(chars.offset() + rest.len() - rest.trim_start().len());
+    }
+    else if rest.starts_with(';') {
+      chars.seek(chars.offset() + rest.find('\n').unwrap_or(rest.len()));
+    }
+    else if rest.starts_with("#|") {
+      if let Err(err) = skip_block_comment(chars) {
+	reader.recover(err)?;
+      }
+    }
+    else if rest.starts_with("#;") {
+      let start = chars.offset();
+      chars.seek(start + 2);
+      skip_blank_with(chars, reader)?;
+      if chars.as_str().is_empty() {
+	return reader.recover(SymParseError::DatumCommentEOF(chars.span_from(start)));
+      }
+      SymItem::read(chars, reader)?;
+    }
+    else {
+      return Ok(());
+    }
+  }
+}
+
+fn skip_block_comment(chars: &mut SourceChars) -> Result<(), SymParseError> {
+  let start = chars.offset();
+  chars.seek(start + 2);
+  let mut depth = 1;
+  while depth > 0 {
+    let rest = chars.as_str();
+    if rest.starts_with("#|") {
+      depth += 1;
+      chars.seek(chars.offset() + 2);
+    }
+    else if rest.starts_with("|#") {
+      depth -= 1;
+      chars.seek(chars.offset() + 2);
+    }
+    else if chars.next().is_none() {
+      return Err(SymParseError::UnterminatedBlockComment(chars.span_from(start)));
+    }
+  }
+  Ok(())
 }
 
 #[derive(Debug, Clone)]
 pub enum SymItem {
   SymList(SymList),
   SymAtom(SymAtom),
+  SymStr(SymStr),
+  // Placeholder for text the recovering reader could not read.
+  SymError(Span),
 }
 
 impl SymItem {
-  pub const Nil : SymItem = SymItem::SymList(SymList { items: vec![] } );
+  #[allow(non_upper_case_globals)]
+  pub const Nil : SymItem = SymItem::SymList(SymList { items: vec![], tail: None, delimiter: Delimiter::Paren, span: None } );
   
-  pub fn parse(string: &str) -> Option<Self> {
-    let mut chars = string.trim().chars();
-    let result = Self::try_from(chars.by_ref());
-    let end_of_input_check = if chars.as_str().len() != 0 {
-      Err(SymParseError::SymItemExtraInput("Extra input after SymItem.".to_string()))
+  pub fn parse(string: &str) -> Result<Self, ParseError> {
+    let mut chars = SourceChars::new(string);
+    skip_blank(&mut chars)?;
+    let result = Self::try_from(&mut chars)?;
+    skip_blank(&mut chars)?;
+    if !chars.as_str().is_empty() {
+      let start = chars.offset();
+      chars.seek(string.trim_end().len());
+      return Err(SymParseError::SymItemExtraInput("Extra input after SymItem.".to_string(), chars.span_from(start)).into());
     }
-    else {
-      Ok(())
-    };
 
-    match result.and_then(|ok| end_of_input_check.and(Ok(ok))) {
-      Ok(x) => Some(x),
-      Err(e) => {
-	println!("Parse failed: {:?}", e);
-	None
-      },
-    }
+    Ok(result)
   }
 
   pub fn is_atom(&self) -> bool {
+    matches!(self, Self::SymAtom(_))
+  }
+
+  pub fn is_list(&self) -> bool {
+    matches!(self, Self::SymList(_))
+  }
+
+  pub fn is_str(&self) -> bool {
+    matches!(self, Self::SymStr(_))
+  }
+
+  // Name of a symbol. Numeric atoms are not symbols; see `as_number`.
+  pub fn as_str(&self) -> Option<&str> {
     match self {
-      Self::SymAtom(_) => true,
-      _ => false,
+      Self::SymAtom(atom) if atom.number.is_none() => Some(atom.as_str()),
+      _ => None,
     }
   }
 
-  pub fn is_list(&self) -> bool {
+  pub fn as_symbol(&self) -> Option<Symbol> {
     match self {
-      Self::SymList(_) => true,
-      _ => false,
+      Self::SymAtom(atom) if atom.number.is_none() => Some(atom.symbol),
+      _ => None,
     }
   }
 
-  pub fn as_str(&self) -> Option<&str> {
+  pub fn as_number(&self) -> Option<Number> {
     if let Self::SymAtom(atom) = self {
-      Some(atom.as_str())
+      atom.number
+    }
+    else {
+      None
+    }
+  }
+
+  pub fn as_integer(&self) -> Option<i64> {
+    self.as_number()?.as_integer()
+  }
+
+  // Contents of a string literal, escapes resolved.
+  pub fn as_text(&self) -> Option<&str> {
+    if let Self::SymStr(string) = self {
+      Some(string.as_str())
     }
     else {
       None
@@ -81,13 +279,23 @@ impl SymItem {
 
   pub fn as_list(&self) -> Option<&SymList> {
     if let Self::SymList(list) = self {
-      Some(&list)
+      Some(list)
     }
     else {
       None
     }
   }
 
+  // Where the item was read from; `None` for items built by the machine.
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      SymItem::SymAtom(atom) => atom.span,
+      SymItem::SymList(list) => list.span,
+      SymItem::SymStr(string) => string.span,
+      SymItem::SymError(span) => Some(*span),
+    }
+  }
+
   pub fn index_early(&self, idx: usize) -> Option<&SymItem> {
     let list = self.as_list()?;
     list[idx].into_inner_early()
@@ -105,39 +313,122 @@ impl Display for SymItem {
       SymItem::SymAtom(data) => {
 	fmt.write_str(data.as_str())
       },
+      SymItem::SymStr(data) => {
+	write!(fmt, "{}", data)
+      },
+      SymItem::SymError(_) => {
+	fmt.write_str("#<error>")
+      },
       SymItem::SymList(data) => {
-	if data.len() == 0 {
-	  fmt.write_str("()")
+	let (open, close) = (data.delimiter.open(), data.delimiter.close());
+	if data.is_empty() {
+	  write!(fmt, "{}{}", open, close)
 	}
 	else {
-	  let inner_fmts = data.iter().map(|x| { format!("{}", &x) }).collect::<Vec<String>>();
+	  let inner_fmts = data.iter().map(|x| { format!("{}", x) }).collect::<Vec<String>>();
 	  let inner_string = format!("{}{}",
 				     inner_fmts[0],
 				     inner_fmts[1..].iter().fold("".to_string(), |acc, x| { format!("{} {}", acc, x) }));
-	  fmt.write_str(format!("({})", inner_string).as_str())
+	  let tail_string = data.tail().map_or("".to_string(), |tail| format!(" . {}", tail));
+	  fmt.write_str(format!("{}{}{}{}", open, inner_string, tail_string, close).as_str())
 	}
       },
     }
   }
 }
 
-impl<'chars> TryFrom<&mut Chars<'chars>> for SymItem {
+// Items compare by structure: spans and list delimiters are ignored, numbers
+// compare by value whatever their notation, and a list item compares the same
+// before and after conversion to a machine element. Atoms order before strings,
+// strings before lists and lists before error placeholders, which all compare
+// equal.
+impl SymItem {
+  fn rank(&self) -> u8 {
+    match self {
+      SymItem::SymAtom(_) => 0,
+      SymItem::SymStr(_) => 1,
+      SymItem::SymList(_) => 2,
+      SymItem::SymError(_) => 3,
+    }
+  }
+}
+
+impl Ord for SymItem {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self, other) {
+      (SymItem::SymAtom(a), SymItem::SymAtom(b)) => a.cmp(b),
+      (SymItem::SymStr(a), SymItem::SymStr(b)) => a.cmp(b),
+      (SymItem::SymList(a), SymItem::SymList(b)) => a.cmp(b),
+      _ => self.rank().cmp(&other.rank()),
+    }
+  }
+}
+
+impl PartialOrd for SymItem {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for SymItem {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for SymItem {}
+
+impl Hash for SymItem {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.rank().hash(state);
+    match self {
+      SymItem::SymAtom(atom) => atom.hash(state),
+      SymItem::SymStr(string) => string.hash(state),
+      SymItem::SymList(list) => list.hash(state),
+      SymItem::SymError(_) => (),
+    }
+  }
+}
+
+impl<'chars> TryFrom<&mut SourceChars<'chars>> for SymItem {
   type Error = SymParseError;
 
-  fn try_from(chars : &mut Chars<'chars>) -> Result<Self, Self::Error> {
+  fn try_from(chars : &mut SourceChars<'chars>) -> Result<Self, Self::Error> {
+    Self::read(chars, &mut Reader::strict())
+  }
+}
+
+impl SymItem {
+  pub(super) fn read(chars : &mut SourceChars, reader : &mut Reader) -> Result<Self, SymParseError> {
+    let start = chars.offset();
     let first_char = chars.clone()
       .peekable().nth(0)
-      .ok_or(SymParseError::SymItemEOF("Empty input when building SymItem.".to_string()))?;
+      .ok_or(SymParseError::SymItemEOF("Empty input when building SymItem.".to_string(), chars.here()))?;
     let result = {
-      if first_char == '('  {
-	let end_of_list_error = Err(SymParseError::SymListEOF("Unexpected EOF after start of SymList.".to_string()));
-	Ok(SymItem::SymList(SymList::new(chars.advance_by(1).or(end_of_list_error).and(Ok(chars.by_ref()))?)?))
+      if let Some(delimiter) = Delimiter::from_open(first_char) {
+	let end_of_list_error = Err(SymParseError::SymListEOF("Unexpected EOF after start of SymList.".to_string(), chars.here()));
+	chars.advance_by(1).or(end_of_list_error)?;
+	Ok(SymItem::SymList(SymList::new(chars, delimiter, reader)?))
+      }
+      else if let Some((name, prefix_len)) = quote_prefix(chars.as_str()) {
+	Ok(SymItem::SymList(SymList::quoted(chars, name, prefix_len, reader)?))
       }
-      else if first_char == ')' {
-	Err(SymParseError::InvalidStartOfInput)
+      else if first_char == '"' {
+	SymStr::read(chars, reader)
+      }
+      else if Delimiter::from_close(first_char).is_some() {
+	chars.next();
+	reader.recover(SymParseError::InvalidStartOfInput(first_char, chars.span_from(start)))?;
+	Ok(SymItem::SymError(chars.span_from(start)))
       }
       else {
-	Ok(SymItem::SymAtom(SymAtom::new(chars.by_ref())?))
+	match SymAtom::new(chars) {
+	  Ok(atom) => Ok(SymItem::SymAtom(atom)),
+	  Err(err) => {
+	    reader.recover(err)?;
+	    Ok(SymItem::SymError(chars.span_from(start)))
+	  },
+	}
       }
     };
 
@@ -168,14 +459,6 @@ impl SymListItem {
     if let SymListItem::Full(x) = self { Some(x) }
     else { None }
   }
-
-  pub fn update_to_late_stage(&self, contents: MetaElement) {
-    let item_ptr : *const SymListItem = self;
-    let item_mut_ptr = item_ptr as *mut SymListItem;
-    unsafe {
-      *item_mut_ptr = SymListItem::Full(contents);
-    }
-  }
 }
 
 // impl SymListItem {
@@ -190,19 +473,112 @@ impl SymListItem {
 //   }
 // }
 
+impl SymListItem {
+  // The expression the item holds whatever its stage; `None` for instructions.
+  fn as_expr(&self) -> Option<&SymItem> {
+    match self {
+      SymListItem::Early(sym) | SymListItem::Full(MetaElement::Expr(sym)) => Some(sym),
+      SymListItem::Full(MetaElement::Instr(_, _)) => None,
+    }
+  }
+}
+
+impl Ord for SymListItem {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self.as_expr(), other.as_expr()) {
+      (Some(a), Some(b)) => a.cmp(b),
+      _ => self.into_inner().cmp(&other.into_inner()),
+    }
+  }
+}
+
+impl PartialOrd for SymListItem {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for SymListItem {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for SymListItem {}
+
+impl Hash for SymListItem {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    match self.as_expr() {
+      Some(sym) => MetaElement::hash_expr(sym, state),
+      None => self.into_inner().hash(state),
+    }
+  }
+}
+
 impl Display for SymListItem {
   fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     match self {
-      SymListItem::Early(symitem) => fmt.write_str(format!("{}", &symitem).as_str())?,
-      SymListItem::Full(meta) => fmt.write_str(format!("{}", &meta).as_str())?
+      SymListItem::Early(symitem) => fmt.write_str(format!("{}", symitem).as_str())?,
+      SymListItem::Full(meta) => fmt.write_str(format!("{}", meta).as_str())?
     }
     Ok(())
   }
 }
 
+// Bracket pair a list was written with. All three read as lists; the delimiter
+// only matters for printing the list back the way it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+  Paren,
+  Bracket,
+  Brace,
+}
+
+impl Delimiter {
+  pub fn from_open(c: char) -> Option<Self> {
+    match c {
+      '(' => Some(Delimiter::Paren),
+      '[' => Some(Delimiter::Bracket),
+      '{' => Some(Delimiter::Brace),
+      _ => None,
+    }
+  }
+
+  pub fn from_close(c: char) -> Option<Self> {
+    match c {
+      ')' => Some(Delimiter::Paren),
+      ']' => Some(Delimiter::Bracket),
+      '}' => Some(Delimiter::Brace),
+      _ => None,
+    }
+  }
+
+  pub fn open(&self) -> char {
+    match self {
+      Delimiter::Paren => '(',
+      Delimiter::Bracket => '[',
+      Delimiter::Brace => '{',
+    }
+  }
+
+  pub fn close(&self) -> char {
+    match self {
+      Delimiter::Paren => ')',
+      Delimiter::Bracket => ']',
+      Delimiter::Brace => '}',
+    }
+  }
+}
+
+// A list, optionally improper: `(a b . c)` holds the items `a` and `b` and the tail
+// `c`. The tail is never itself a list, since `(a . (b c))` reads as `(a b c)`, and
+// a list with a tail always has at least one item.
 #[derive(Debug, Clone)]
 pub struct SymList {
   items : Vec<SymListItem>,
+  tail : Option<Box<SymListItem>>,
+  delimiter : Delimiter,
+  span : Option<Span>,
 }
 
 impl Deref for SymList {
@@ -262,58 +638,420 @@ impl Deref for SymList {
 // }
 
 impl SymList {
-  fn new(chars : &mut Chars) -> Result<Self, SymParseError> {
-    let list_eof_error = SymParseError::SymListEOF("EOF when building SymList.".to_string());
+  pub fn from_elements(elements : Vec<MetaElement>) -> Self {
+    Self::from_parts(elements, None)
+  }
+
+  // `tail` must not be a list and needs at least one element before it; see
+  // `MetaElement::from_parts` for a constructor that normalizes.
+  pub fn from_parts(elements : Vec<MetaElement>, tail : Option<MetaElement>) -> Self {
+    SymList {
+      items : elements.into_iter().map(SymListItem::Full).collect(),
+      tail : tail.map(|tail| Box::new(SymListItem::Full(tail))),
+      delimiter : Delimiter::Paren,
+      span : None,
+    }
+  }
+
+  pub fn span(&self) -> Option<Span> {
+    self.span
+  }
+
+  // A copy of the list with every early item converted by `convert`, for building
+  // the machine element tree next to the parsed one. Items that already are
+  // elements are kept as they are.
+  pub fn to_late_stage<'a, E>(&'a self, mut convert : impl FnMut(&'a SymItem) -> Result<MetaElement, E>) -> Result<Self, E> {
+    let mut late = |item : &'a SymListItem| match item {
+      SymListItem::Early(sym) => convert(sym).map(SymListItem::Full),
+      SymListItem::Full(elem) => Ok(SymListItem::Full(elem.clone())),
+    };
+    Ok(SymList {
+      items : self.items.iter().map(&mut late).collect::<Result<_, _>>()?,
+      tail : self.tail.as_deref().map(&mut late).transpose()?.map(Box::new),
+      delimiter : self.delimiter,
+      span : self.span,
+    })
+  }
+
+  // The item after the dot of an improper list.
+  pub fn tail(&self) -> Option<&SymListItem> {
+    self.tail.as_deref()
+  }
+
+  pub fn is_proper(&self) -> bool {
+    self.tail.is_none()
+  }
+
+  pub fn delimiter(&self) -> Delimiter {
+    self.delimiter
+  }
+
+  pub fn with_delimiter(mut self, delimiter : Delimiter) -> Self {
+    self.delimiter = delimiter;
+    self
+  }
+
+  // Reads a quote prefix and the item after it as `(name item)`.
+  fn quoted(chars : &mut SourceChars, name : Symbol, prefix_len : usize, reader : &mut Reader) -> Result<Self, SymParseError> {
+    let start = chars.offset();
+    chars.seek(start + prefix_len);
+    let head = SymAtom {
+      symbol : name,
+      number : None,
+      span : Some(chars.span_from(start)),
+    };
+
+    skip_blank_with(chars, reader)?;
+    let item = if chars.as_str().is_empty() {
+      reader.recover(SymParseError::QuoteEOF(chars.span_from(start)))?;
+      SymItem::SymError(chars.span_from(start))
+    }
+    else {
+      SymItem::read(chars, reader)?
+    };
+
+    Ok(SymList {
+      items : vec![SymListItem::Early(SymItem::SymAtom(head)), SymListItem::Early(item)],
+      tail : None,
+      delimiter : Delimiter::Paren,
+      span : Some(chars.span_from(start)),
+    })
+  }
+
+  // Called with the opening bracket already consumed. When recovering, a list cut
+  // short by the end of the input keeps the items read so far and a mismatched
+  // bracket closes the list anyway.
+  fn new(chars : &mut SourceChars, delimiter : Delimiter, reader : &mut Reader) -> Result<Self, SymParseError> {
+    let start = chars.offset() - 1;
     let mut list_items = Vec::new();
+    let mut list_tail = None;
 
-    while chars.as_str().get(0..1).ok_or(list_eof_error.clone())? != ")" {
-      list_items.push(SymListItem::Early(SymItem::try_from(chars.by_ref())?));
-
-      // let test = [SymItem::parse("a").unwrap(),
-      // 		  SymItem::parse("b").unwrap()];
-      // let test1 = [SymListItem::Early(SymItem::parse("a").unwrap()),
-      // 	       	   SymListItem::Early(SymItem::parse("b").unwrap())];
-      // let test2 = [SymListItem::Early(SymItem::parse("a").unwrap()),
-      // 	       	   SymListItem::Early(SymItem::parse("b").unwrap())];
-      // println!("0x{:#x}", get_dist(&test[1], &test[0]));
-      // println!("0x{:#x}", get_dist(&test1[1], &test1[0]));
-      // println!("0x{:#x}", std::mem::size_of::<SymItem>());
-      // println!("0x{:#x}", std::mem::size_of::<SymListItem>());
-      // println!("{}", std::mem::align_of::<SymItem>());
-      // println!("{}", std::mem::align_of::<SymListItem>());
-      // panic!("hi");
-
-      let next_elem_start = chars.as_str().find(|c| { c != ' ' && c != '\n' }).ok_or(list_eof_error.clone())?;
-      if next_elem_start > 0 {
-	chars.nth(next_elem_start - 1).unwrap();
+    let closed = loop {
+      skip_blank_with(chars, reader)?;
+      match chars.as_str().chars().next() {
+	None => break false,
+	Some(c) if Delimiter::from_close(c).is_some() => break true,
+	Some(_) if is_dot(chars.as_str()) => {
+	  let dot_start = chars.offset();
+	  chars.next();
+	  skip_blank_with(chars, reader)?;
+	  let tail = match chars.as_str().chars().next() {
+	    None => None,
+	    Some(c) if Delimiter::from_close(c).is_some() => None,
+	    Some(_) => Some(SymItem::read(chars, reader)?),
+	  };
+	  skip_blank_with(chars, reader)?;
+	  let at_end = chars.as_str().is_empty();
+	  let closed = chars.as_str().starts_with(|c| Delimiter::from_close(c).is_some());
+	  match tail {
+	    Some(SymItem::SymList(list)) if (closed || at_end) && !list_items.is_empty() => {
+	      list_items.extend(list.items);
+	      list_tail = list.tail;
+	    },
+	    Some(tail) if (closed || at_end) && !list_items.is_empty() => {
+	      list_tail = Some(Box::new(SymListItem::Early(tail)));
+	    },
+	    _ if at_end => (),
+	    tail => {
+	      // Read on as if the dot were not there.
+	      reader.recover(SymParseError::InvalidDottedList(chars.span_from(dot_start)))?;
+	      let dot_span = Span { start: chars.location(dot_start), end: chars.location(dot_start + 1) };
+	      list_items.push(SymListItem::Early(SymItem::SymError(dot_span)));
+	      list_items.extend(tail.map(SymListItem::Early));
+	      continue;
+	    },
+	  }
+	  break closed;
+	},
+	Some(_) => list_items.push(SymListItem::Early(SymItem::read(chars, reader)?)),
+      }
+    };
+    if closed {
+      let close_start = chars.offset();
+      let close = chars.next().unwrap();
+      if close != delimiter.close() {
+	let open_span = Span { start: chars.location(start), end: chars.location(start + 1) };
+	reader.recover(SymParseError::MismatchedBracket(delimiter.open(), open_span, close, chars.span_from(close_start)))?;
       }
     }
-    chars.next();
+    else {
+      reader.recover(SymParseError::SymListEOF("EOF when building SymList.".to_string(), chars.span_from(start)))?;
+    }
 
     Ok(SymList {
       items : list_items,
+      tail : list_tail,
+      delimiter,
+      span : Some(chars.span_from(start)),
     })
   }
 }
 
+// Lists compare item by item, then by tail.
+impl Ord for SymList {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.items.cmp(&other.items).then_with(|| self.tail.cmp(&other.tail))
+  }
+}
+
+impl PartialOrd for SymList {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for SymList {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for SymList {}
+
+impl Hash for SymList {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.items.hash(state);
+    self.tail.hash(state);
+  }
+}
+
+// A lone `.` separates the items of an improper list from its tail.
+fn is_dot(rest: &str) -> bool {
+  let mut chars = rest.chars();
+  chars.next() == Some('.') && chars.next().is_none_or(ends_atom)
+}
+
+// Atoms run up to any whitespace, a bracket, a string literal or the start of
+// a line comment.
+pub(super) fn ends_atom(c: char) -> bool {
+  c.is_whitespace() || Delimiter::from_open(c).is_some() || Delimiter::from_close(c).is_some() || c == '"' || c == ';'
+}
+
+// A bare atom. `symbol` keeps the text as written; atoms that read as numbers
+// also carry their value.
 #[derive(Debug, Clone)]
 pub struct SymAtom {
-  symbol : String,
+  symbol : Symbol,
+  number : Option<Number>,
+  span : Option<Span>,
 }
 
 impl Deref for SymAtom {
-  type Target = String;
+  type Target = str;
 
   fn deref(&self) -> &Self::Target {
-    &self.symbol
+    self.symbol.as_str()
   }
 }
 
 impl SymAtom {
-  fn new(chars : &mut Chars) -> Result<Self, SymParseError> {
-    let sym_end = chars.as_str().find(|c: char| { c == '(' || c == ')' || c == ' ' }).unwrap_or(chars.as_str().len());
+  pub fn symbol(&self) -> Symbol {
+    self.symbol
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    self.symbol.as_str()
+  }
+
+  pub fn span(&self) -> Option<Span> {
+    self.span
+  }
+
+  pub fn number(&self) -> Option<Number> {
+    self.number
+  }
+
+  // An atom with no source position, as when it was not read from text.
+  pub fn from_symbol(symbol : Symbol) -> Self {
+    SymAtom {
+      symbol,
+      number : Number::read(symbol.as_str()).ok().flatten(),
+      span : None,
+    }
+  }
+
+  fn new(chars : &mut SourceChars) -> Result<Self, SymParseError> {
+    let start = chars.offset();
+    let sym_end = chars.as_str().find(ends_atom).unwrap_or(chars.as_str().len());
+    let text = &chars.as_str()[..sym_end];
+    chars.seek(start + sym_end);
+    let number = Number::read(text).map_err(|()| SymParseError::InvalidNumber(chars.span_from(start)))?;
     Ok(SymAtom {
-      symbol : chars.take(sym_end).collect::<String>(),
+      symbol : Symbol::intern(text),
+      number,
+      span : Some(chars.span_from(start)),
     })
   }
 }
+
+// Numbers order before symbols. Symbols hash by name, so hashes do not depend on
+// interning order either.
+impl Ord for SymAtom {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self.number, other.number) {
+      (Some(a), Some(b)) => a.cmp(&b),
+      (Some(_), None) => Ordering::Less,
+      (None, Some(_)) => Ordering::Greater,
+      (None, None) => self.symbol.cmp(&other.symbol),
+    }
+  }
+}
+
+impl PartialOrd for SymAtom {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for SymAtom {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for SymAtom {}
+
+impl Hash for SymAtom {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    match self.number {
+      Some(number) => number.hash(state),
+      None => self.as_str().hash(state),
+    }
+  }
+}
+
+// A double-quoted string literal. Holds the text with its escapes resolved; the
+// escapes are `\n`, `\t`, `\r`, `\0`, `\"`, `\\` and `\u{...}` with a hex code point.
+#[derive(Debug, Clone)]
+pub struct SymStr {
+  text : String,
+  span : Option<Span>,
+}
+
+impl Deref for SymStr {
+  type Target = String;
+
+  fn deref(&self) -> &Self::Target {
+    &self.text
+  }
+}
+
+impl SymStr {
+  pub fn span(&self) -> Option<Span> {
+    self.span
+  }
+
+  pub fn from_text(text : String) -> Self {
+    SymStr { text, span : None }
+  }
+
+  // Called at the opening quote. When recovering, a literal with a bad escape or
+  // no closing quote reads as a `SymItem::SymError`.
+  fn read(chars : &mut SourceChars, reader : &mut Reader) -> Result<SymItem, SymParseError> {
+    let start = chars.offset();
+    chars.next();
+    let mut text = String::new();
+    let mut valid = true;
+    loop {
+      let escape_start = chars.offset();
+      match chars.next() {
+	None => {
+	  reader.recover(SymParseError::SymStrEOF(chars.span_from(start)))?;
+	  return Ok(SymItem::SymError(chars.span_from(start)));
+	},
+	Some('"') => break,
+	Some('\\') => {
+	  let escaped = match chars.next() {
+	    Some('n') => Some('\n'),
+	    Some('t') => Some('\t'),
+	    Some('r') => Some('\r'),
+	    Some('0') => Some('\0'),
+	    Some('"') => Some('"'),
+	    Some('\\') => Some('\\'),
+	    Some('u') => Self::unicode_escape(chars),
+	    Some(_) => None,
+	    None => {
+	      reader.recover(SymParseError::SymStrEOF(chars.span_from(start)))?;
+	      return Ok(SymItem::SymError(chars.span_from(start)));
+	    },
+	  };
+	  match escaped {
+	    Some(c) => text.push(c),
+	    None => {
+	      reader.recover(SymParseError::InvalidEscape(chars.span_from(escape_start)))?;
+	      valid = false;
+	    },
+	  }
+	},
+	Some(c) => text.push(c),
+      }
+    }
+
+    if !valid {
+      return Ok(SymItem::SymError(chars.span_from(start)));
+    }
+    Ok(SymItem::SymStr(SymStr {
+      text,
+      span : Some(chars.span_from(start)),
+    }))
+  }
+
+  // The `{...}` part of a `\u{...}` escape: one to six hex digits naming a code point.
+  // Whether it is valid or not, the braces are consumed, up to the end of the input
+  // when the closing one is missing.
+  fn unicode_escape(chars : &mut SourceChars) -> Option<char> {
+    let rest = chars.as_str();
+    let len = rest.strip_prefix('{').map_or(0, |inner| inner.find('}').map_or(rest.len(), |idx| idx + 2));
+    chars.seek(chars.offset() + len);
+    let digits = rest[..len].strip_prefix('{')?.strip_suffix('}')?;
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+      return None;
+    }
+    char::from_u32(u32::from_str_radix(digits, 16).ok()?)
+  }
+}
+
+impl Ord for SymStr {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.text.cmp(&other.text)
+  }
+}
+
+impl PartialOrd for SymStr {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for SymStr {
+  fn eq(&self, other: &Self) -> bool {
+    self.text == other.text
+  }
+}
+
+impl Eq for SymStr {}
+
+impl Hash for SymStr {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.text.hash(state);
+  }
+}
+
+impl Display for SymStr {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fmt.write_str("\"")?;
+    for c in self.text.chars() {
+      match c {
+	'\n' => fmt.write_str("\\n")?,
+	'\t' => fmt.write_str("\\t")?,
+	'\r' => fmt.write_str("\\r")?,
+	'\0' => fmt.write_str("\\0")?,
+	'"' => fmt.write_str("\\\"")?,
+	'\\' => fmt.write_str("\\\\")?,
+	c if c.is_control() => write!(fmt, "\\u{{{:x}}}", c as u32)?,
+	c => fmt.write_char(c)?,
+      }
+    }
+    fmt.write_str("\"")
+  }
+}
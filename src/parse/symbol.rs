@@ -0,0 +1,99 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+use std::sync::{LazyLock, RwLock};
+
+// An interned name. Every distinct atom text is stored once for the life of the
+// program and a `Symbol` is its index, so symbols are `Copy` and compare, hash and
+// match as integers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// Names the reader and the machine dispatch on, interned up front so they can be
+// matched as constants. Their ids are their positions in this list.
+const PREDEFINED: [&str; 11] = [
+  "quote", "quasiquote", "unquote", "unquote-splicing",
+  ".DEFINE", ".EXPAND", ".INDEX", ".CONTEXT", ".RETURN", ".FRAME", ".SPLIT",
+];
+
+// Names of the forms the quote prefixes `'`, `` ` ``, `,` and `,@` read as.
+pub const QUOTE: Symbol = Symbol(0);
+pub const QUASIQUOTE: Symbol = Symbol(1);
+pub const UNQUOTE: Symbol = Symbol(2);
+pub const UNQUOTE_SPLICING: Symbol = Symbol(3);
+
+impl Symbol {
+  // Machine instruction names.
+  pub const DEFINE: Symbol = Symbol(4);
+  pub const EXPAND: Symbol = Symbol(5);
+  pub const INDEX: Symbol = Symbol(6);
+  pub const CONTEXT: Symbol = Symbol(7);
+  pub const RETURN: Symbol = Symbol(8);
+  pub const FRAME: Symbol = Symbol(9);
+  pub const SPLIT: Symbol = Symbol(10);
+
+  pub fn intern(name: &str) -> Self {
+    if let Some(&symbol) = INTERNER.read().unwrap().ids.get(name) {
+      return symbol;
+    }
+    INTERNER.write().unwrap().insert(name)
+  }
+
+  pub fn as_str(self) -> &'static str {
+    INTERNER.read().unwrap().names[self.0 as usize]
+  }
+}
+
+// Symbols order by name rather than id, so the order does not depend on what was
+// interned first.
+impl Ord for Symbol {
+  fn cmp(&self, other: &Self) -> Ordering {
+    if self == other { Ordering::Equal } else { self.as_str().cmp(other.as_str()) }
+  }
+}
+
+impl PartialOrd for Symbol {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Display for Symbol {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fmt.write_str(self.as_str())
+  }
+}
+
+impl Debug for Symbol {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    write!(fmt, "Symbol({:?})", self.as_str())
+  }
+}
+
+// Names are leaked so that `Symbol::as_str` can hand them out without holding the
+// lock; the table only ever grows.
+struct Interner {
+  ids: HashMap<&'static str, Symbol>,
+  names: Vec<&'static str>,
+}
+
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(|| {
+  let mut interner = Interner { ids: HashMap::new(), names: vec![] };
+  for name in PREDEFINED {
+    interner.insert(name);
+  }
+  RwLock::new(interner)
+});
+
+impl Interner {
+  fn insert(&mut self, name: &str) -> Symbol {
+    if let Some(&symbol) = self.ids.get(name) {
+      return symbol;
+    }
+    let name: &'static str = Box::leak(name.into());
+    let symbol = Symbol(u32::try_from(self.names.len()).expect("Symbol table overflow."));
+    self.names.push(name);
+    self.ids.insert(name, symbol);
+    symbol
+  }
+}
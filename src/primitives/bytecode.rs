@@ -0,0 +1,209 @@
+use super::element::MetaElement;
+use super::minst::{DecodingError, EncodingError, MInstEncoding, MacroInstruction};
+use crate::parse::{Span, SymItem, Symbol, QUASIQUOTE, QUOTE, UNQUOTE, UNQUOTE_SPLICING};
+
+// A macro body lowered to a flat, position-addressed instruction stream. Each
+// element of the body is one word: instructions are stored encoded, and every
+// other element, such as a nested macro call or a literal, as a reference into
+// the constant pool. The spans of the elements are kept alongside, so runtime
+// errors still point into the source.
+#[derive(Debug, Clone)]
+pub struct Bytecode {
+  code: Vec<MInstEncoding>,
+  ops: Vec<Operation>,
+  spans: Vec<Option<Span>>,
+  constants: Vec<MetaElement>,
+}
+
+// What running one word of a body comes down to. Words are decoded and constants
+// sorted out once, when the bytecode is built, so the machine dispatches on them
+// directly. Constant operations carry their index into the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+  Instr(MacroInstruction),
+  // A string or number literal, pushed as it is.
+  Literal(usize),
+  // A `quote` form, whose operand is pushed.
+  Quote(usize),
+  // A call of the named macro: a bare symbol, or a proper list headed by one.
+  Call(Symbol, usize),
+  // Anything else, evaluated as an element the way the tree walker would, such as
+  // quasiquote templates or instructions too large for a word.
+  Eval(usize),
+}
+
+impl Operation {
+  fn classify(elem: &MetaElement, index: usize) -> Self {
+    let list = match elem {
+      MetaElement::Expr(SymItem::SymStr(_)) => return Operation::Literal(index),
+      MetaElement::Expr(sym) if sym.as_number().is_some() => return Operation::Literal(index),
+      MetaElement::Expr(_) => elem.as_list(),
+      MetaElement::Instr(_, _) => return Operation::Eval(index),
+    };
+    let list = match list {
+      Some(list) if list.is_proper() => list,
+      Some(_) => return Operation::Eval(index),
+      None => return elem.as_symbol().map_or(Operation::Eval(index), |name| Operation::Call(name, index)),
+    };
+    match list.head().and_then(|head| head.as_symbol()) {
+      Some(QUOTE) if list.len() == 2 => Operation::Quote(index),
+      Some(name) if ![QUASIQUOTE, UNQUOTE, UNQUOTE_SPLICING].contains(&name) || list.len() != 2 => Operation::Call(name, index),
+      _ => Operation::Eval(index),
+    }
+  }
+}
+
+impl Bytecode {
+  // Compiles the elements of a body, in execution order. Instructions with
+  // arguments too large for an instruction word go to the constant pool as they
+  // are, so any body compiles.
+  pub fn compile(body: &[MetaElement]) -> Result<Self, EncodingError> {
+    let mut bytecode = Bytecode { code: vec![], ops: vec![], spans: vec![], constants: vec![] };
+    for elem in body {
+      let encoded = match elem {
+	MetaElement::Instr(inst, _) => MInstEncoding::try_from(inst.clone()).ok().map(|word| (word, inst)),
+	MetaElement::Expr(_) => None,
+      };
+      match encoded {
+	Some((word, inst)) => {
+	  bytecode.code.push(word);
+	  bytecode.ops.push(Operation::Instr(inst.clone()));
+	},
+	None => bytecode.push_constant(elem.clone())?,
+      }
+      bytecode.spans.push(elem.span());
+    }
+    Ok(bytecode)
+  }
+
+  // Rebuilds a body from instruction words whose constant references index
+  // `pool`. Only the constants the words refer to are kept, and there are no
+  // spans to go with them.
+  pub fn decode(words: &[u32], pool: &[MetaElement]) -> Result<Self, DecodingError> {
+    let mut bytecode = Bytecode { code: vec![], ops: vec![], spans: vec![], constants: vec![] };
+    for &word in words {
+      let word = MInstEncoding::from_word(word);
+      match word.constant_index() {
+	Some(index) => {
+	  let constant = pool.get(index).ok_or(DecodingError::InvalidConstant(index))?;
+	  bytecode.push_constant(constant.clone()).map_err(|_| DecodingError::InvalidConstant(index))?;
+	},
+	None => {
+	  bytecode.ops.push(Operation::Instr(MacroInstruction::try_from(word)?));
+	  bytecode.code.push(word);
+	},
+      }
+      bytecode.spans.push(None);
+    }
+    Ok(bytecode)
+  }
+
+  fn push_constant(&mut self, elem: MetaElement) -> Result<(), EncodingError> {
+    let index = self.constants.len();
+    self.code.push(MInstEncoding::constant(index)?);
+    self.ops.push(Operation::classify(&elem, index));
+    self.constants.push(elem);
+    Ok(())
+  }
+
+  pub fn len(&self) -> usize {
+    self.code.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.code.is_empty()
+  }
+
+  pub fn code(&self) -> &[MInstEncoding] {
+    &self.code
+  }
+
+  pub fn constants(&self) -> &[MetaElement] {
+    &self.constants
+  }
+
+  pub fn operation(&self, pc: usize) -> &Operation {
+    &self.ops[pc]
+  }
+
+  pub fn span(&self, pc: usize) -> Option<Span> {
+    self.spans[pc]
+  }
+
+  // The element at `pc`, rebuilt for display and for saving.
+  pub fn fetch(&self, pc: usize) -> MetaElement {
+    match &self.ops[pc] {
+      Operation::Instr(inst) => MetaElement::Instr(inst.clone(), self.spans[pc]),
+      Operation::Literal(index) | Operation::Quote(index) | Operation::Call(_, index) | Operation::Eval(index) =>
+	self.constants[*index].clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine::tests::{boot, run};
+  use crate::machine::ExecutionMode;
+
+  #[test]
+  fn bytecode_mode() {
+    let body = MetaElement::parse("((.SPLIT 1) \"lit\" (car x) (.RETURN 2 -1) (.EXPAND 9000))").unwrap();
+    let code = Bytecode::compile(&body.elements().unwrap()).unwrap();
+    assert_eq!((code.len(), code.constants().len()), (5, 3));
+    assert_eq!(code.code()[4].constant_index(), Some(2));
+    assert_eq!(code.fetch(4), MetaElement::parse("(.EXPAND 9000)").unwrap());
+    assert_eq!(code.operation(0), &Operation::Instr(MacroInstruction::Split{narg: 1}));
+    assert_eq!(code.operation(1), &Operation::Literal(0));
+    assert_eq!(code.operation(2), &Operation::Call(Symbol::intern("car"), 1));
+    assert_eq!(code.operation(4), &Operation::Eval(2));
+    assert_eq!(MInstEncoding::constant(MInstEncoding::CONSTANT_MAX).unwrap().constant_index(), Some(MInstEncoding::CONSTANT_MAX));
+    assert!(MInstEncoding::constant(MInstEncoding::CONSTANT_MAX + 1).is_err());
+
+    let program = "
+      (macro (car x) (.SPLIT 1) (.RETURN -2))
+      (macro (cdr x) (.SPLIT 1) (.RETURN -1))
+      (macro (pair a b) `(,a . ,b) (.RETURN -1))
+      (macro (second x) (.INDEX 0 1) (.SPLIT -1) (.SPLIT -1) (.RETURN -2))
+      (macro (wrap x) \"lit\" 42 'q (pair x (car (1 2))) (.RETURN 2 -1))
+      (car (1 2)) (cdr (1 2 3)) (pair x y) (second (a b c)) (wrap z)";
+    let run_in = |mode, program| {
+      let mut meta = boot();
+      meta.set_execution_mode(mode);
+      run(&mut meta, program)
+    };
+    assert_eq!(run_in(ExecutionMode::Tree, program).as_deref(), Ok("(1 (2 3) (x . y) b (\"lit\" 42 q (x car (1 2))))"));
+    assert_eq!(run_in(ExecutionMode::Bytecode, program), run_in(ExecutionMode::Tree, program));
+  }
+
+  // Each kind of constant sorts into the operation that runs it like the tree
+  // walker would, errors included.
+  #[test]
+  fn operations() {
+    let body = MetaElement::parse("('(a b) sym (f . x) () ((f) x) `(,x) ,x (quote a b) (.RETURN 9000 1))").unwrap();
+    let code = Bytecode::compile(&body.elements().unwrap()).unwrap();
+    let ops = (0..code.len()).map(|pc| code.operation(pc).clone()).collect::<Vec<_>>();
+    assert_eq!(ops, [Operation::Quote(0), Operation::Call(Symbol::intern("sym"), 1), Operation::Eval(2), Operation::Eval(3),
+		     Operation::Eval(4), Operation::Eval(5), Operation::Eval(6), Operation::Call(QUOTE, 7), Operation::Eval(8)]);
+
+    let programs = [
+      "(macro (f x) '(a b) (.RETURN -1)) (f y)",
+      "(macro (f x) (g x)) (f y)",
+      "(macro (g) (.RETURN 0)) (macro (f x) g (.RETURN -1)) (f y)",
+      "(macro (f x) (f . x)) (f y)",
+      "(macro (f x) ((f) x)) (f y)",
+      "(macro (f x) ()) (f y)",
+      "(macro (f x) ,x) (f y)",
+      "(macro (f x) (quote a b)) (f y)",
+      "(macro (g a b) (.RETURN 1)) (macro (f x) (g x)) (f y)",
+    ];
+    for program in programs {
+      let run_in = |mode| {
+	let mut meta = boot();
+	meta.set_execution_mode(mode);
+	run(&mut meta, program)
+      };
+      assert_eq!(run_in(ExecutionMode::Bytecode), run_in(ExecutionMode::Tree), "program {:?}", program);
+    }
+  }
+}
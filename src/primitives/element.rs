@@ -1,17 +1,36 @@
 use super::minst::{MacroInstruction, MinstSymItemError};
-use crate::parse::{SymItem, SymList, SymAtom};
+use crate::parse::{Number, ParseError, Symbol, SymItem, SymList, Span};
 
 use std::vec;
+use std::cmp::Ordering;
 use std::fmt::{self, Display, Debug};
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
 #[derive(Debug)]
 pub enum MetaElementError<'m> {
-  ParseError,
   InvalidInstr(&'m SymItem),
   UnknownMinstError(MinstSymItemError<'m>),
 }
 
+impl<'m> MetaElementError<'m> {
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      MetaElementError::InvalidInstr(sym) => sym.span(),
+      MetaElementError::UnknownMinstError(err) => err.span(),
+    }
+  }
+}
+
+impl<'m> From<MetaElementError<'m>> for ParseError {
+  fn from(err : MetaElementError<'m>) -> Self {
+    match err {
+      MetaElementError::InvalidInstr(sym) => ParseError::InvalidInstr(sym.clone()),
+      MetaElementError::UnknownMinstError(err) => ParseError::from(err),
+    }
+  }
+}
+
 impl<'m> From<MinstSymItemError<'m>> for MetaElementError<'m> {
   fn from(err : MinstSymItemError<'m>) -> Self {
     match err {
@@ -23,76 +42,122 @@ impl<'m> From<MinstSymItemError<'m>> for MetaElementError<'m> {
 
 #[derive(Debug, Clone)]
 pub enum MetaElement {
-  Instr(MacroInstruction),
+  Instr(MacroInstruction, Option<Span>),
   Expr(SymItem),
 }
 
 impl MetaElement {
-  pub fn parse(string : &str) -> Option<Self> {
-    let expr = SymItem::parse(string).ok_or(MetaElementError::ParseError)
-      .map(|val| { Some(val) })
-      .unwrap_or_else(|err| { println!("SymItem parse failed {:?}", &err); None })?;
+  pub fn parse(string : &str) -> Result<Self, ParseError> {
+    let expr = SymItem::parse(string)?;
+    Ok(MetaElement::try_from(&expr)?)
+  }
+
+  pub fn from_elements(elements : Vec<MetaElement>) -> Self {
+    MetaElement::Expr(SymItem::SymList(SymList::from_elements(elements)))
+  }
 
-    MetaElement::try_from(&expr)
-      .map(|val| { Some(val) })
-      .unwrap_or_else(|err| { println!("MetaElement conversion failed: {:?}", &err); None })
+  // Builds `(elements... . tail)`. A list tail is spliced in, so the result reads
+  // back the same way, and with no elements the result is the tail itself.
+  pub fn from_parts(mut elements : Vec<MetaElement>, tail : Option<MetaElement>) -> Self {
+    let tail = match tail.as_ref().and_then(|tail| tail.as_list()) {
+      Some(list) => {
+	let dotted_tail = list.dotted_tail().cloned();
+	elements.extend(list.into_iter().cloned());
+	dotted_tail
+      },
+      None => tail,
+    };
+
+    match (elements.is_empty(), tail) {
+      (true, Some(tail)) => tail,
+      (_, tail) => MetaElement::Expr(SymItem::SymList(SymList::from_parts(elements, tail))),
+    }
   }
 
   pub fn as_str(&self) -> Option<&str> {
     if let MetaElement::Expr(symitem) = self {
-      if let SymItem::SymAtom(atom) = symitem {
-	Some(atom.as_str())
-      }
-      else { None }
+      symitem.as_str()
+    }
+    else { None }
+  }
+
+  pub fn as_symbol(&self) -> Option<Symbol> {
+    if let MetaElement::Expr(symitem) = self {
+      symitem.as_symbol()
+    }
+    else { None }
+  }
+
+  pub fn as_number(&self) -> Option<Number> {
+    if let MetaElement::Expr(symitem) = self {
+      symitem.as_number()
+    }
+    else { None }
+  }
+
+  // Contents of a string literal. Strings are values of their own and never name
+  // a definition, so `as_str` does not see them.
+  pub fn as_text(&self) -> Option<&str> {
+    if let MetaElement::Expr(symitem) = self {
+      symitem.as_text()
     }
     else { None }
   }
 
   pub fn as_list<'a>(&'a self) -> Option<MetaElementListOperator<'a>> {
-    if let MetaElement::Expr(expr) = self {
-      if let SymItem::SymList(list) = expr {
-	Some(MetaElementListOperator::new(&list))
-      }
-      else { None }
+    if let MetaElement::Expr(SymItem::SymList(list)) = self {
+      Some(MetaElementListOperator::new(list))
     }
     else { None }
   }
+
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      MetaElement::Instr(_, span) => *span,
+      MetaElement::Expr(sym) => sym.span(),
+    }
+  }
+
+  // Items of a proper list; `None` for anything else, improper lists included.
+  pub fn elements(&self) -> Option<Vec<MetaElement>> {
+    self.as_list()
+      .filter(|list| list.is_proper())
+      .map(|list| list.into_iter().cloned().collect())
+  }
 }
 
 impl<'m> TryFrom<&'m SymItem> for MetaElement {
   type Error = MetaElementError<'m>;
   fn try_from(sym : &'m SymItem) -> Result<Self, Self::Error> {
-    if sym.is_atom() {
+    if !sym.is_list() {
       Ok(MetaElement::Expr(sym.clone()))
     }
     else {
-      if sym.as_list().unwrap().len() >= 1 {
+      if !sym.as_list().unwrap().is_empty() {
 	let car = sym.index_early(0).unwrap();
 	// Try making a machine instruction
 	let try_inst = {
-	  if car.is_atom() {
+	  if car.as_str().is_some() {
 	    MacroInstruction::try_from(sym)
 	  }
 	  else {
-	    Err(MinstSymItemError::NotAnInstruction(&sym))
+	    Err(MinstSymItemError::NotAnInstruction(sym))
 	  }
 	};
 
 	match try_inst {
-	  Ok(inst) => return Ok(MetaElement::Instr(inst)),
+	  Ok(inst) => return Ok(MetaElement::Instr(inst, sym.span())),
 	  Err(ref err) => {
 	    match err {
 	      MinstSymItemError::NotAnInstruction(_) => (),
-	      _ => { try_inst?; () },
+	      _ => { try_inst?; },
 	    }
 	  }
 	}
 
 	// Machine instruction didn't work out, recurse on list
-	for item in sym.as_list().unwrap().iter() {
-	  item.update_to_late_stage(MetaElement::try_from(item.into_inner_early().unwrap())?);
-        }
-	Ok(MetaElement::Expr(sym.clone()))
+	let list = sym.as_list().unwrap().to_late_stage(MetaElement::try_from)?;
+	Ok(MetaElement::Expr(SymItem::SymList(list)))
       }
       else {
 	Ok(MetaElement::Expr(sym.clone()))
@@ -101,15 +166,64 @@ impl<'m> TryFrom<&'m SymItem> for MetaElement {
   }
 }
     
+// Elements compare by structure like `SymItem`, ignoring spans. Expressions order
+// before instructions, and an instruction never equals the list it was read from.
+impl Ord for MetaElement {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self, other) {
+      (MetaElement::Expr(a), MetaElement::Expr(b)) => a.cmp(b),
+      (MetaElement::Instr(a, _), MetaElement::Instr(b, _)) => a.cmp(b),
+      (MetaElement::Expr(_), MetaElement::Instr(_, _)) => Ordering::Less,
+      (MetaElement::Instr(_, _), MetaElement::Expr(_)) => Ordering::Greater,
+    }
+  }
+}
+
+impl PartialOrd for MetaElement {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for MetaElement {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for MetaElement {}
+
+impl Hash for MetaElement {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    match self {
+      MetaElement::Expr(sym) => Self::hash_expr(sym, state),
+      MetaElement::Instr(inst, _) => {
+	1u8.hash(state);
+	inst.hash(state);
+      },
+    }
+  }
+}
+
+impl MetaElement {
+  // Hashes `sym` as `MetaElement::Expr(sym)` would be, for list items that have
+  // not been converted yet.
+  pub(crate) fn hash_expr<H: Hasher>(sym: &SymItem, state: &mut H) {
+    0u8.hash(state);
+    sym.hash(state);
+  }
+}
+
 impl Display for MetaElement {
   fn fmt(&self, fmt : &mut fmt::Formatter) -> Result<(), fmt::Error> {
     match self {
-      MetaElement::Expr(sym) => fmt.write_str(format!("{}", &sym).as_str()),
-      MetaElement::Instr(inst) => fmt.write_str(format!("{}", &inst).as_str()),
+      MetaElement::Expr(sym) => fmt.write_str(format!("{}", sym).as_str()),
+      MetaElement::Instr(inst, _) => fmt.write_str(format!("{}", inst).as_str()),
     }
   }
 }
 
+#[derive(Clone, Copy)]
 pub struct MetaElementListOperator<'a> {
   list: &'a SymList,
 }
@@ -117,11 +231,30 @@ pub struct MetaElementListOperator<'a> {
 impl<'a> MetaElementListOperator<'a> {
   fn new(list: &'a SymList) -> Self {
     MetaElementListOperator {
-      list: list,
+      list,
     }
   }
 }
 
+impl<'a> MetaElementListOperator<'a> {
+  // First item, the `car`.
+  pub fn head(&self) -> Option<&'a MetaElement> {
+    self.list.first().map(|item| item.into_inner().unwrap())
+  }
+
+  // Everything after the first item, the `cdr`: the remaining items with the same
+  // tail, the tail alone when a single item precedes it, or `()`.
+  pub fn tail(&self) -> Option<MetaElement> {
+    let rest = self.list.get(1..)?.iter().map(|item| item.into_inner().unwrap().clone()).collect();
+    Some(MetaElement::from_parts(rest, self.dotted_tail().cloned()))
+  }
+
+  // The item after the dot of an improper list.
+  pub fn dotted_tail(&self) -> Option<&'a MetaElement> {
+    self.list.tail().map(|tail| tail.into_inner().unwrap())
+  }
+}
+
 impl<'a> IntoIterator for MetaElementListOperator<'a> {
   type Item = &'a MetaElement;
   type IntoIter = vec::IntoIter<Self::Item>;
@@ -138,7 +271,7 @@ impl<'a> Deref for MetaElementListOperator<'a> {
   type Target = SymList;
 
   fn deref(&self) -> &Self::Target {
-    &self.list
+    self.list
   }
 }
 
@@ -1,20 +1,47 @@
 use std::convert::TryFrom;
+use std::error::Error;
 use std::fmt::{self, Display};
 
-use crate::parse::SymItem;
+use crate::parse::{ParseError, Symbol, SymItem, Span};
 
-#[derive(Debug)]
-enum EncodingError {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
   InvalidArg(i32),
+  InvalidRange(i32, i32),
+  InvalidConstant(usize),
 }
 
-#[derive(Debug)]
-enum DecodingError {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodingError {
   InvalidInstEncoding(u32),
   InvalidInstWithArgs(u32),
-  InvalidContextRange(i32, i32),
+  InvalidConstant(usize),
 }
 
+impl Display for EncodingError {
+  fn fmt(&self, fmt : &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      EncodingError::InvalidArg(arg) => write!(fmt, "instruction argument {} cannot be encoded", arg),
+      EncodingError::InvalidRange(start, end) => write!(fmt, "`.RETURN` range {} {} cannot be encoded", start, end),
+      EncodingError::InvalidConstant(index) => write!(fmt, "constant index {} cannot be encoded", index),
+    }
+  }
+}
+
+impl Error for EncodingError {}
+
+impl Display for DecodingError {
+  fn fmt(&self, fmt : &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      DecodingError::InvalidInstEncoding(opcode) => write!(fmt, "unknown instruction opcode {}", opcode),
+      DecodingError::InvalidInstWithArgs(word) => write!(fmt, "instruction word {:#010x} has malformed arguments", word),
+      DecodingError::InvalidConstant(index) => write!(fmt, "constant index {} is outside the constant pool", index),
+    }
+  }
+}
+
+impl Error for DecodingError {}
+
 #[derive(Debug, Clone)]
 pub enum MinstSymItemError<'a> {
   NotAnInstruction(&'a SymItem),
@@ -22,16 +49,40 @@ pub enum MinstSymItemError<'a> {
   InvalidArgs(&'a SymItem),
 }
 
-#[derive(Debug, Clone)]
+impl<'a> From<MinstSymItemError<'a>> for ParseError {
+  fn from(err : MinstSymItemError<'a>) -> Self {
+    match err {
+      MinstSymItemError::NotAnInstruction(sym) => ParseError::NotAnInstruction(sym.clone()),
+      MinstSymItemError::InvalidInstr(sym) => ParseError::InvalidInstr(sym.clone()),
+      MinstSymItemError::InvalidArgs(sym) => ParseError::InvalidArgs(sym.clone()),
+    }
+  }
+}
+
+impl<'a> MinstSymItemError<'a> {
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      MinstSymItemError::NotAnInstruction(sym) => sym.span(),
+      MinstSymItemError::InvalidInstr(sym) => sym.span(),
+      MinstSymItemError::InvalidArgs(sym) => sym.span(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum MacroInstruction {
-  Define,
-  Expand,
+  Define{frame: Option<i32>},
+  Expand{narg: Option<i32>},
   Index{frame: i32, narg: Option<i32>},
   Context{range: Option<(i32, i32)>},
   Return{range: Option<ReturnInstData>},
+  // `frame` is an element of the active frame, not a frame of the stack as with
+  // `.INDEX`; the list there replaces the active frame.
+  Frame{frame: i32, narg: Option<i32>},
+  Split{narg: i32},
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ReturnInstData {
     Arg(i32),
     Range(i32, i32),
@@ -41,62 +92,31 @@ impl TryFrom<MInstEncoding> for MacroInstruction {
   type Error = DecodingError;
 
   fn try_from(inst : MInstEncoding) -> Result<Self, Self::Error> {
-    let word = inst.inst;
-    let inst_encoding = word & 0x7;
-    let inst_variation = (word & 0x80000000) != 0;
-    let (inst_arg0, inst_arg1) = ((word >> 3 & 0x3FFF) as i32, (word >> (14 + 3) & 0x3FFF) as i32);
-
-    let check_null_args = || {
-      let arg_bits = (word >> 3) & 0xFFFFFFF;
-      if !(inst_variation && arg_bits == 0) {
-	Err(DecodingError::InvalidInstWithArgs(word))
-      }
-      else {
-	Ok(())
-      }
-    };
+    let word = inst.word();
+    let opcode = word & 0x7;
+    let long = word & MInstEncoding::LONG != 0;
+    let (arg0, arg1) = inst.args();
 
-    // Functions to map function arguments to packaged MacroInstruction arguments
-    let arg_map_index = |arg: i32| -> Result<Option<i32>, DecodingError> {
-      if inst_variation { Ok(Some(arg)) } else { Ok(None) }
+    // Fields the form does not use must be zero.
+    let decoded = match (opcode, long) {
+      (0, false) if arg0 == 0 && arg1 == 0 => Some(MacroInstruction::Define{frame: None}),
+      (0, true) if arg1 == 0 => Some(MacroInstruction::Define{frame: Some(arg0)}),
+      (1, false) if arg0 == 0 && arg1 == 0 => Some(MacroInstruction::Expand{narg: None}),
+      (1, true) if arg1 == 0 => Some(MacroInstruction::Expand{narg: Some(arg0)}),
+      (2, false) if arg1 == 0 => Some(MacroInstruction::Index{frame: arg0, narg: None}),
+      (2, true) => Some(MacroInstruction::Index{frame: arg0, narg: Some(arg1)}),
+      (3, false) if arg0 == 0 && arg1 == 0 => Some(MacroInstruction::Context{range: None}),
+      (3, true) => Some(MacroInstruction::Context{range: Some((arg0, arg1))}),
+      (4, false) if arg0 == 0 && arg1 == 0 => Some(MacroInstruction::Return{range: None}),
+      (4, true) if arg0 == arg1 => Some(MacroInstruction::Return{range: Some(ReturnInstData::Arg(arg0))}),
+      (4, true) => Some(MacroInstruction::Return{range: Some(ReturnInstData::Range(arg0, arg1))}),
+      (5, false) if arg1 == 0 => Some(MacroInstruction::Frame{frame: arg0, narg: None}),
+      (5, true) => Some(MacroInstruction::Frame{frame: arg0, narg: Some(arg1)}),
+      (6, false) if arg1 == 0 => Some(MacroInstruction::Split{narg: arg0}),
+      (0..=6, _) => None,
+      _ => return Err(DecodingError::InvalidInstEncoding(opcode)),
     };
-    let arg_map_context = |arg0: i32, arg1: i32| -> Result<Option<(i32, i32)>, DecodingError> {
-      if inst_variation {
-	if arg0 == arg1 {
-	  Err(DecodingError::InvalidContextRange(arg0, arg1))
-	}
-	else {
-	  Ok(Some((arg0, arg1)))
-	}
-      }
-      else {
-	Ok(None)
-      }
-    };
-    let arg_map_return = |start: i32, end: i32| -> Result<Option<ReturnInstData>, DecodingError> {
-      if inst_variation {
-	if start == end {
-	  Ok(Some(ReturnInstData::Arg(start)))
-	}
-	else {
-	  Ok(Some(ReturnInstData::Range(start, end)))
-	}
-      }
-      else {
-	Ok(None)
-      }
-    };    
-
-    // Construct the instruction
-    check_null_args()?;
-    match inst_encoding {
-      0 => Ok(MacroInstruction::Define),
-      1 => Ok(MacroInstruction::Expand),
-      2 => Ok(MacroInstruction::Index{frame: inst_arg0, narg: arg_map_index(inst_arg1)?}),
-      3 => Ok(MacroInstruction::Context{range: arg_map_context(inst_arg0, inst_arg1)?}),
-      4 => Ok(MacroInstruction::Return{range: arg_map_return(inst_arg0, inst_arg1)?}),
-      _ => Err(DecodingError::InvalidInstEncoding(inst_encoding)),
-    }
+    decoded.ok_or(DecodingError::InvalidInstWithArgs(word))
   }
 }
 
@@ -104,39 +124,50 @@ impl<'m> TryFrom<&'m SymItem> for MacroInstruction {
   type Error = MinstSymItemError<'m>;
 
   fn try_from(sym : &'m SymItem) -> Result<Self, Self::Error> {
-    let inst_name = sym.index_early(0).unwrap().as_str().unwrap();
+    let inst_name = sym.index_early(0).unwrap().as_symbol().unwrap();
 
     // Check for machine instruction dot
-    if inst_name.chars().nth(0).unwrap() != '.' {
+    if !inst_name.as_str().starts_with('.') {
       Err(MinstSymItemError::NotAnInstruction(sym.index_early(0).unwrap()))?
     }
+    if !sym.as_list().unwrap().is_proper() {
+      Err(MinstSymItemError::InvalidInstr(sym))?
+    }
 
     // Convert arguments into integers
     let args_as_integers = sym.as_list().unwrap().
       iter().skip(1)
       .map(|sym| {
 	let inner_sym = sym.into_inner_early().unwrap();
-	inner_sym.as_str().unwrap()
-	  .parse::<i32>().or(Err(MinstSymItemError::InvalidArgs(inner_sym)))
+	inner_sym.as_integer().and_then(|arg| i32::try_from(arg).ok())
+	  .ok_or(MinstSymItemError::InvalidArgs(inner_sym))
       }).try_collect::<Vec<i32>>()?;
     let num_args = args_as_integers.len();
 
     // Dispatch create MacroInstructions based off of the first symbol name
-    let test = Err(MinstSymItemError::InvalidInstr(sym));
     match inst_name {
-      ".DEFINE" => {
-	if num_args != 0 { Err(MinstSymItemError::InvalidInstr(sym)) }
-	else { Ok(MacroInstruction::Define) }
+      Symbol::DEFINE => {
+	match num_args {
+	  0 => Ok(MacroInstruction::Define{frame: None}),
+	  1 => Ok(MacroInstruction::Define{frame: Some(args_as_integers[0])}),
+	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
+	}
+      },
+      Symbol::EXPAND => {
+	match num_args {
+	  0 => Ok(MacroInstruction::Expand{narg: None}),
+	  1 => Ok(MacroInstruction::Expand{narg: Some(args_as_integers[0])}),
+	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
+	}
       },
-      ".EXPAND" => test,
-      ".CONTEXT" => {
+      Symbol::CONTEXT => {
 	match num_args {
 	  0 => Ok(MacroInstruction::Context{range: None}),
 	  2 => Ok(MacroInstruction::Context{range: Some((args_as_integers[0], args_as_integers[1]))}),
 	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
 	}
       },
-      ".INDEX" => {
+      Symbol::INDEX => {
 	match num_args {
 	  0 => Err(MinstSymItemError::InvalidArgs(sym)),
 	  1 => Ok(MacroInstruction::Index{frame: args_as_integers[0], narg: None}),
@@ -144,7 +175,7 @@ impl<'m> TryFrom<&'m SymItem> for MacroInstruction {
 	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
 	}
       },
-      ".RETURN" => {
+      Symbol::RETURN => {
 	match num_args {
 	  0 => Ok(MacroInstruction::Return{range: None}),
 	  1 => Ok(MacroInstruction::Return{range: Some(ReturnInstData::Arg(args_as_integers[0]))}),
@@ -159,6 +190,20 @@ impl<'m> TryFrom<&'m SymItem> for MacroInstruction {
 	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
 	}
       },
+      Symbol::FRAME => {
+	match num_args {
+	  0 => Err(MinstSymItemError::InvalidArgs(sym)),
+	  1 => Ok(MacroInstruction::Frame{frame: args_as_integers[0], narg: None}),
+	  2 => Ok(MacroInstruction::Frame{frame: args_as_integers[0], narg: Some(args_as_integers[1])}),
+	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
+	}
+      },
+      Symbol::SPLIT => {
+	match num_args {
+	  1 => Ok(MacroInstruction::Split{narg: args_as_integers[0]}),
+	  _ => Err(MinstSymItemError::InvalidInstr(sym)),
+	}
+      },
       _ => Err(MinstSymItemError::InvalidInstr(sym)),
     }
   }
@@ -167,11 +212,13 @@ impl<'m> TryFrom<&'m SymItem> for MacroInstruction {
 impl From<&MacroInstruction> for u32 {
   fn from(inst: &MacroInstruction) -> u32 {
     match inst {
-      MacroInstruction::Define => 0,
-      MacroInstruction::Expand => 1,
+      MacroInstruction::Define{frame: _} => 0,
+      MacroInstruction::Expand{narg: _} => 1,
       MacroInstruction::Index{frame: _, narg: _} => 2,
       MacroInstruction::Context{range: _} => 3,
       MacroInstruction::Return{range: _} => 4,
+      MacroInstruction::Frame{frame: _, narg: _} => 5,
+      MacroInstruction::Split{narg: _} => 6,
     }
   }
 }
@@ -179,8 +226,18 @@ impl From<&MacroInstruction> for u32 {
 impl Display for MacroInstruction {
   fn fmt(&self, fmt : &mut fmt::Formatter) -> Result<(), fmt::Error> {
     match self {
-      MacroInstruction::Define => fmt.write_str("(.DEFINE)"),
-      MacroInstruction::Expand => fmt.write_str("(.EXPAND)"),
+      MacroInstruction::Define{frame} => {
+	match frame {
+	  Some(frame) => fmt.write_str(format!("(.DEFINE {})", frame).as_str()),
+	  None => fmt.write_str("(.DEFINE)"),
+	}
+      },
+      MacroInstruction::Expand{narg} => {
+	match narg {
+	  Some(narg) => fmt.write_str(format!("(.EXPAND {})", narg).as_str()),
+	  None => fmt.write_str("(.EXPAND)"),
+	}
+      },
       MacroInstruction::Index{frame, narg} => {
 	match narg {
 	  Some(narg) => fmt.write_str(format!("(.INDEX {} {})", frame, narg).as_str()),
@@ -190,7 +247,7 @@ impl Display for MacroInstruction {
       MacroInstruction::Context{range} => {
 	match range {
 	  Some(range) => fmt.write_str(format!("(.CONTEXT {} {})", range.0, range.1).as_str()),
-	  None => fmt.write_str(format!("(.CONTEXT)").as_str()),
+	  None => fmt.write_str("(.CONTEXT)"),
 	}
       },
       MacroInstruction::Return{range} => {
@@ -204,86 +261,160 @@ impl Display for MacroInstruction {
 	  None => fmt.write_str("(.RETURN)"),
 	}
       },
+      MacroInstruction::Frame{frame, narg} => {
+	match narg {
+	  Some(narg) => fmt.write_str(format!("(.FRAME {} {})", frame, narg).as_str()),
+	  None => fmt.write_str(format!("(.FRAME {})", frame).as_str()),
+	}
+      },
+      MacroInstruction::Split{narg} => fmt.write_str(format!("(.SPLIT {})", narg).as_str()),
     }
   }
 }
 
-#[derive(Debug)]
-struct MInstEncoding {
+// A machine instruction packed into a 32-bit word:
+//
+//   bit  31     30..17   16..3   2..0
+//        long   arg1     arg0    opcode
+//
+// Opcodes 0 to 6 are .DEFINE, .EXPAND, .INDEX, .CONTEXT, .RETURN, .FRAME and
+// .SPLIT. The arguments are 14-bit two's complement fields, from `ARG_MIN` to
+// `ARG_MAX`. Bit 31 selects the long form of an instruction:
+//
+//   .DEFINE, .EXPAND   no arguments, or long with the argument in arg0
+//   .INDEX, .FRAME     frame in arg0, or long with the element index in arg1 too
+//   .CONTEXT           no arguments, or long with the range in arg0 and arg1
+//   .RETURN            no arguments, or long with the range in arg0 and arg1;
+//                      equal bounds stand for the one-argument form
+//   .SPLIT             element index in arg0; there is no long form
+//
+// Fields a form does not use are zero. The reader accepts any 32-bit argument,
+// so an instruction only encodes when its arguments fit the fields; one that
+// does decodes back to the same instruction.
+//
+// Opcode 7 is no instruction. Compiled bodies use it for references into their
+// constant pool, with the index in bits 31..3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MInstEncoding {
   inst : u32,
 }
 
+impl MInstEncoding {
+  pub const ARG_MIN : i32 = -0x2000;
+  pub const ARG_MAX : i32 = 0x1FFF;
+  pub const CONSTANT_MAX : usize = (u32::MAX >> 3) as usize;
+  const LONG : u32 = 1 << 31;
+  const CONSTANT : u32 = 7;
+
+  pub fn from_word(word : u32) -> Self {
+    MInstEncoding { inst : word }
+  }
+
+  pub fn word(&self) -> u32 {
+    self.inst
+  }
+
+  pub fn constant(index : usize) -> Result<Self, EncodingError> {
+    if index > Self::CONSTANT_MAX {
+      return Err(EncodingError::InvalidConstant(index));
+    }
+    Ok(MInstEncoding { inst : (index as u32) << 3 | Self::CONSTANT })
+  }
+
+  // The constant pool index of a constant reference.
+  pub fn constant_index(&self) -> Option<usize> {
+    (self.inst & 0x7 == Self::CONSTANT).then_some((self.inst >> 3) as usize)
+  }
+
+  fn field(arg : i32) -> Result<u32, EncodingError> {
+    if !(Self::ARG_MIN..=Self::ARG_MAX).contains(&arg) {
+      return Err(EncodingError::InvalidArg(arg));
+    }
+    Ok(arg as u32 & 0x3FFF)
+  }
+
+  // Both argument fields, sign-extended.
+  fn args(&self) -> (i32, i32) {
+    (((self.inst << 15) as i32) >> 18, ((self.inst << 1) as i32) >> 18)
+  }
+}
+
 impl TryFrom<MacroInstruction> for MInstEncoding {
   type Error = EncodingError;
 
-  fn try_from(inst_type : MacroInstruction) -> Result<Self, Self::Error> {
-    let (inst_enc, inst_args) = {
-      let arg_vec = match inst_type {
-	MacroInstruction::Define => vec![],
-	MacroInstruction::Expand => vec![],
-	MacroInstruction::Index{frame, narg} => {
-	  match narg {
-	    Some(narg) => vec![frame, narg],
-	    None => vec![frame],
-	  }
+  fn try_from(inst : MacroInstruction) -> Result<Self, Self::Error> {
+    let (long, arg0, arg1) = match inst {
+      MacroInstruction::Define{frame} => (frame.is_some(), frame.unwrap_or(0), 0),
+      MacroInstruction::Expand{narg} => (narg.is_some(), narg.unwrap_or(0), 0),
+      MacroInstruction::Index{frame, narg} | MacroInstruction::Frame{frame, narg} => (narg.is_some(), frame, narg.unwrap_or(0)),
+      MacroInstruction::Context{range} => (range.is_some(), range.map_or(0, |range| range.0), range.map_or(0, |range| range.1)),
+      MacroInstruction::Return{range: None} => (false, 0, 0),
+      MacroInstruction::Return{range: Some(ReturnInstData::Arg(arg))} => (true, arg, arg),
+      MacroInstruction::Return{range: Some(ReturnInstData::Range(start, end))} => {
+	if start == end {
+	  return Err(EncodingError::InvalidRange(start, end));
 	}
-	MacroInstruction::Context{range} => {
-	  match range {
-	    Some((x0, x1)) => vec![x0, x1],
-	    None => vec![],
-	  }
-	},
-	MacroInstruction::Return{ref range} => {
-	  match range {
-	    Some(range) => {
-	      match range {
-		ReturnInstData::Arg(arg) => vec![*arg],
-		ReturnInstData::Range(arg0, arg1) => vec![*arg0, *arg1],
-	      }
-	    },
-	    None => vec![],
-	  }
-	},
-      };
-
-      (u32::from(&inst_type), arg_vec)
+	(true, start, end)
+      },
+      MacroInstruction::Split{narg} => (false, narg, 0),
     };
 
-    // let arg_bounds = (0xFFF as i32, -1 * 0x1000 as i32);
-    let arg_mask = 0x3FFF;
-    let inst_data = (||{
-      match inst_args.len() {
-	0 => Ok(0),
-	_ => {
-	  let failed_args = inst_args.iter().filter_map(|arg| {
-	    if *arg & !arg_mask > 0 { Some(arg.clone()) } else { None }
-	  }).collect::<Vec<i32>>();
-
-	  if failed_args.len() > 0 {
-	    Err(EncodingError::InvalidArg(failed_args[0]))
-	  }
-	  else {
-	    Ok((inst_args[0] & arg_mask) << 3 | (inst_args[1] & arg_mask) << (3 + 14))
-	  }
-	},
+    let long = if long { Self::LONG } else { 0 };
+    Ok(MInstEncoding { inst : long | Self::field(arg1)? << 17 | Self::field(arg0)? << 3 | u32::from(&inst) })
+  }
+}
+
+impl From<MInstEncoding> for u32 {
+  fn from(inst : MInstEncoding) -> u32 {
+    inst.word()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::primitives::MetaElement;
+
+  #[test]
+  fn instruction_encoding() {
+    let sources = ["(.DEFINE)", "(.DEFINE -3)", "(.EXPAND)", "(.EXPAND 8191)", "(.INDEX 0)", "(.INDEX 0 1)",
+		   "(.CONTEXT)", "(.CONTEXT 1 1)", "(.CONTEXT -1 2)", "(.RETURN)", "(.RETURN -1)", "(.RETURN 1 -1)",
+		   "(.FRAME -8192)", "(.FRAME 2 -8192)", "(.SPLIT 1)", "(.SPLIT -1)"];
+    for source in sources {
+      let inst = match MetaElement::parse(source).unwrap() {
+	MetaElement::Instr(inst, _) => inst,
+	elem => panic!("{} is not an instruction", elem),
+      };
+      let word = MInstEncoding::try_from(inst.clone()).unwrap();
+      assert_eq!(MacroInstruction::try_from(word), Ok(inst), "{} as {:#010x}", source, word.word());
+    }
+
+    // Every word that decodes encodes back to itself.
+    let fields = [0, 1, 0x1FFF, 0x2000, 0x3FFF];
+    for opcode in 0..8 {
+      for (arg0, arg1, long) in fields.iter().flat_map(|arg0| fields.iter().flat_map(move |arg1| [(arg0, arg1, 0), (arg0, arg1, 1 << 31)])) {
+	let word = long | arg1 << 17 | arg0 << 3 | opcode;
+	if let Ok(inst) = MacroInstruction::try_from(MInstEncoding::from_word(word)) {
+	  assert_eq!(MInstEncoding::try_from(inst).map(u32::from), Ok(word));
+	}
       }
-    })()?;
-    // let inst_data = (||{
-    //   if inst_args.len() == 1 {
-    // 	let 
-    // 	let x0 = inst_args[0];
-    // 	if x0 > arg_bounds.0 || x0 < arg_bounds.1 { Err(EncodingError::InvalidArg(x0))? }
-    // 	return Ok((x0 & 0xFFFF) << 3)
-    //   }
-    //   if inst_args.len() == 2 {
-    // 	let (x0, x1) = (inst_args[0], inst_args[1]);
-    // 	if x0 > 0xFFF || x0 < (-1 * 0x1000) { Err(EncodingError::InvalidArg(x0))? }
-    // 	if x1 > 0xFFF || x1 < (-1 * 0x1000) { Err(EncodingError::InvalidArg(x1))? }
-    // 	return Ok((x0 << 3) | (x1 << (13 + 3)))
-    //   }
-    //   return Ok(0)
-    // })()?;
-
-    Ok(MInstEncoding { inst : inst_enc | inst_data as u32})
+    }
+
+    // The reader takes any 32-bit argument, but only 14 bits fit in a word.
+    for (source, arg) in [("(.INDEX 0 9000)", 9000), ("(.EXPAND -8193)", -8193), ("(.RETURN 2147483647)", i32::MAX)] {
+      let inst = match MetaElement::parse(source).unwrap() {
+	MetaElement::Instr(inst, _) => inst,
+	elem => panic!("{} is not an instruction", elem),
+      };
+      assert_eq!(MInstEncoding::try_from(inst), Err(EncodingError::InvalidArg(arg)), "{}", source);
+    }
+    assert_eq!(MInstEncoding::try_from(MacroInstruction::Split{narg: 8192}), Err(EncodingError::InvalidArg(8192)));
+    assert_eq!(MInstEncoding::try_from(MacroInstruction::Index{frame: 0, narg: Some(-8193)}), Err(EncodingError::InvalidArg(-8193)));
+    let range = MacroInstruction::Return{range: Some(ReturnInstData::Range(2, 2))};
+    assert_eq!(MInstEncoding::try_from(range), Err(EncodingError::InvalidRange(2, 2)));
+    assert_eq!(MacroInstruction::try_from(MInstEncoding::from_word(7)), Err(DecodingError::InvalidInstEncoding(7)));
+    for word in [6 | 1 << 31, 1 << 3, 2 | 1 << 17] {
+      assert_eq!(MacroInstruction::try_from(MInstEncoding::from_word(word)), Err(DecodingError::InvalidInstWithArgs(word)));
+    }
   }
 }
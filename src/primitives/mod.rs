@@ -1,7 +1,10 @@
+mod bytecode;
 mod minst;
 mod element;
-mod new;
+// mod new;
 
-// pub use element::{MetaElement};
-pub use new::MetaElement;
+pub use bytecode::{Bytecode, Operation};
+pub use element::{MetaElement, MetaElementError};
+pub use minst::{DecodingError, EncodingError, MInstEncoding, MacroInstruction, MinstSymItemError, ReturnInstData};
+// pub use new::MetaElement;
 
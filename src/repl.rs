@@ -0,0 +1,163 @@
+use syms::diagnostics::Diagnose;
+use syms::machine::{ExecutionMode, MetaMachine};
+use syms::parse::{ParseError, ProgramReader};
+use syms::primitives::MetaElement;
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+pub struct Repl {
+  meta: MetaMachine,
+  buffer: String,
+}
+
+impl Repl {
+  pub fn new() -> Self {
+    Repl {
+      meta: crate::boot(),
+      buffer: String::new(),
+    }
+  }
+
+  pub fn run(&mut self) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+      let prompt = if self.buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+      print!("{}", prompt);
+      io::stdout().flush()?;
+
+      let line = match lines.next() {
+	Some(line) => line?,
+	None => return Ok(()),
+      };
+
+      if self.buffer.is_empty() && line.trim_start().starts_with(':') {
+	self.command(line.trim());
+      }
+      else {
+	self.buffer.push_str(&line);
+	self.buffer.push('\n');
+	let input = std::mem::take(&mut self.buffer);
+	self.buffer = self.eval(&input).to_string();
+      }
+    }
+  }
+
+  fn command(&mut self, line: &str) {
+    let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+    match command {
+      ":defs" => {
+	for def in self.meta.get_defs() {
+	  MetaMachine::print_def(def);
+	}
+      },
+      ":stack" => {
+	for (depth, frame) in self.meta.stack().enumerate() {
+	  println!("{}: {}", depth, MetaElement::from_elements(frame.clone()));
+	}
+      },
+      ":reset" => {
+	let mode = self.meta.execution_mode();
+	self.meta = crate::boot();
+	self.meta.set_execution_mode(mode);
+      },
+      ":mode" => {
+	match arg.trim() {
+	  "" => println!("{:?}", self.meta.execution_mode()),
+	  "tree" => self.meta.set_execution_mode(ExecutionMode::Tree),
+	  "bytecode" => self.meta.set_execution_mode(ExecutionMode::Bytecode),
+	  mode => println!("Unknown mode {}; expected tree or bytecode", mode),
+	}
+      },
+      ":load" => {
+	match fs::read_to_string(arg.trim()) {
+	  Ok(source) => {
+	    match crate::run_source(&mut self.meta, &source) {
+	      Ok(()) => println!("{}", MetaElement::from_elements(self.meta.frame().clone())),
+	      Err(err) => print!("{}", err.render(arg.trim(), Some(&source))),
+	    }
+	  },
+	  Err(err) => println!("Cannot read {}: {}", arg.trim(), err),
+	}
+      },
+      _ => println!("Unknown command {}; expected :defs, :stack, :reset, :mode [tree | bytecode] or :load <file>", command),
+    }
+  }
+
+  // Runs every complete form in `input` and returns the remainder that still
+  // waits for more input, such as a list or block comment left open.
+  fn eval<'a>(&mut self, input: &'a str) -> &'a str {
+    let mut done = 0;
+    for item in ProgramReader::new(input) {
+      let item = match item {
+	Ok(item) => item,
+	Err(err) if err.is_incomplete() => return &input[done..],
+	Err(err) => {
+	  print!("{}", ParseError::from(err).diagnose().render("<repl>", Some(input)));
+	  return "";
+	},
+      };
+      if let Some(span) = item.span() {
+	done = span.end.offset;
+      }
+
+      let elem = match MetaElement::try_from(&item) {
+	Ok(elem) => elem,
+	Err(err) => {
+	  print!("{}", ParseError::from(err).diagnose().render("<repl>", Some(input)));
+	  continue;
+	},
+      };
+      self.meta.feed(elem);
+      match self.meta.run() {
+	Ok(()) => println!("{}", MetaElement::from_elements(self.meta.frame().clone())),
+	// Runtime spans may point into earlier input, so no snippet is quoted.
+	Err(err) => print!("{}", err.diagnose().render("<repl>", None)),
+      }
+    }
+    ""
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame(repl: &Repl) -> String {
+    MetaElement::from_elements(repl.meta.frame().clone()).to_string()
+  }
+
+  // Complete forms run right away; an open one is handed back to wait for the rest.
+  #[test]
+  fn continuation() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.eval("(macro (id x) (.RETURN 1)) (id a) (id"), " (id");
+    assert_eq!(repl.eval(" (id\n #| b |#"), " (id\n #| b |#");
+    assert_eq!(repl.eval(" (id\n #| b |# b)\n"), "");
+    assert_eq!(frame(&repl), "(a b)");
+    // Errors are reported and the rest of the input dropped.
+    assert_eq!(repl.eval("(id c) (id ]"), "");
+    assert_eq!(frame(&repl), "(a b c)");
+  }
+
+  #[test]
+  fn commands() {
+    let path = std::env::temp_dir().join(format!("syms-repl-{}.mmm", std::process::id()));
+    fs::write(&path, "(macro (id x) (.RETURN 1))\n(id loaded)").unwrap();
+
+    let mut repl = Repl::new();
+    repl.command(&format!(":load {}", path.display()));
+    fs::remove_file(&path).unwrap();
+    assert_eq!(frame(&repl), "(loaded)");
+
+    repl.command(":mode bytecode");
+    repl.command(":reset");
+    assert_eq!(frame(&repl), "()");
+    assert!(repl.meta.get_def(syms::parse::Symbol::intern("id")).is_none());
+    assert_eq!(repl.meta.execution_mode(), ExecutionMode::Bytecode);
+  }
+}